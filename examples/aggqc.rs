@@ -0,0 +1,617 @@
+use indexmap::IndexSet;
+use pfhs::{
+    block::BlockHash,
+    certificate_cache::CertificateCache,
+    certificates::{
+        AggQC, CertificateError, QuorumCertificate, VerifyContext, QC,
+    },
+    cluster::DOMAIN,
+    crypto::{PrivateKey, PublicKey, Registration, Signer, Verifier},
+    domain::{SignatureKind, SigningDomain},
+    epoch::EpochSchedule,
+    message::{
+        MessageType, NewView, NewViewSummary, SignedMessage, Vote,
+    },
+    signer::{ConsensusSigner, SignRequest},
+    transaction::Transaction,
+    validator_set::{SignerBitmap, ValidatorSet},
+    vote_aggregator::VoteAggregator,
+};
+
+/// Validly signed QC for `blockhash` in `view` by `signers`, produced
+/// by `producer`
+fn qc(
+    validator_set: &ValidatorSet,
+    view: u64,
+    blockhash: BlockHash,
+    signers: &[PrivateKey],
+    producer: &PrivateKey,
+) -> QC {
+    let vote = Vote { view, blockhash };
+    let signatures: Vec<_> = signers
+        .iter()
+        .map(|key| {
+            SignedMessage::vote(vote.clone(), &DOMAIN, key).signature
+        })
+        .collect();
+    let certificate = QuorumCertificate::from_votes(
+        vote,
+        &signatures,
+        signers
+            .iter()
+            .map(|key| key.public_key())
+            .collect(),
+        validator_set,
+        &DOMAIN,
+        producer,
+    )
+    .unwrap();
+    let QuorumCertificate::Happy(qc) = certificate else {
+        unreachable!()
+    };
+    qc
+}
+
+/// AggQC over the new views for `view` sent by each key with its
+/// certificate, produced by `producer`
+fn aggqc(
+    validator_set: &ValidatorSet,
+    view: u64,
+    producer: &PrivateKey,
+    entries: Vec<(&PrivateKey, QuorumCertificate)>,
+) -> AggQC {
+    let mut etas = vec![];
+    let mut signatures = vec![];
+    let mut signers = IndexSet::new();
+    for (key, certificate) in &entries {
+        let eta = NewView {
+            view,
+            certificate: certificate.clone(),
+        };
+        signatures.push(
+            SignedMessage::new_view(eta.clone(), &DOMAIN, key)
+                .signature,
+        );
+        etas.push(eta);
+        signers.insert(key.public_key());
+    }
+    let certificate = QuorumCertificate::from_newviews(
+        etas,
+        signatures,
+        signers,
+        validator_set,
+        &DOMAIN,
+        producer,
+    )
+    .unwrap();
+    let QuorumCertificate::Sad(aggqc) = certificate else {
+        unreachable!()
+    };
+    aggqc
+}
+
+fn main() {
+    let keys: Vec<PrivateKey> = (0..4)
+        .map(|_| PrivateKey::from_seed(rand::random()))
+        .collect();
+    let validators = &keys;
+    let registrations: Vec<Registration> = validators
+        .iter()
+        .map(Registration::new)
+        .collect();
+    let epochs = EpochSchedule::new(
+        ValidatorSet::from_registrations(registrations.iter().copied())
+            .unwrap(),
+    );
+    let ctx = VerifyContext::new(&epochs, DOMAIN);
+
+    // Keys are only admitted with a proof of possession of their own
+    // secret key
+    let stolen_proof = Registration {
+        public_key: registrations[1].public_key,
+        proof_of_possession: registrations[0].proof_of_possession,
+    };
+    assert!(registrations
+        .iter()
+        .all(Registration::verify));
+    assert!(!stolen_proof.verify());
+    assert!(ValidatorSet::from_registrations([
+        registrations[0],
+        stolen_proof
+    ])
+    .is_none());
+    assert!(
+        !Transaction::reconfiguration([stolen_proof]).verify(&DOMAIN)
+    );
+    assert!(Transaction::reconfiguration(registrations.clone())
+        .verify(&DOMAIN));
+    let leader = |view: u64| {
+        let leader = epochs.validator_set(view).leader(view);
+        validators
+            .iter()
+            .find(|key| key.public_key() == *leader)
+            .unwrap()
+    };
+    // Some validator that is not the leader of `view`
+    let not_leader = |view: u64| {
+        let leader = epochs.validator_set(view).leader(view);
+        validators
+            .iter()
+            .find(|key| key.public_key() != *leader)
+            .unwrap()
+    };
+    let set = epochs.validator_set(0);
+    let genesis = QuorumCertificate::Genesis;
+    let qc_1 =
+        qc(set, 1, BlockHash([1; 32]), &validators[..3], leader(2));
+    let qc_2 =
+        qc(set, 2, BlockHash([2; 32]), &validators[1..], leader(3));
+
+    // Mixed genesis and happy certificates, every claimed view bound
+    // to its signer. Only the high QC, for view 2, is carried.
+    let mixed = aggqc(
+        set,
+        4,
+        leader(4),
+        vec![
+            (&validators[0], genesis.clone()),
+            (&validators[1], QuorumCertificate::Happy(qc_1.clone())),
+            (&validators[2], QuorumCertificate::Happy(qc_2.clone())),
+        ],
+    );
+    assert_eq!(mixed.verify(4, &ctx), Ok(()));
+    assert_eq!(mixed.find_high_qc(), Some(&qc_2.clone().into()));
+    assert!(
+        mixed.verify(5, &ctx).is_err(),
+        "aggregated for another view"
+    );
+
+    // Only genesis certificates: valid, and there is no high QC
+    let all_genesis = aggqc(
+        set,
+        1,
+        leader(1),
+        validators[..3]
+            .iter()
+            .map(|key| (key, genesis.clone()))
+            .collect(),
+    );
+    assert_eq!(all_genesis.verify(1, &ctx), Ok(()));
+    assert_eq!(all_genesis.find_high_qc(), None);
+
+    // Every validator, not just a quorum
+    let everyone = aggqc(
+        set,
+        3,
+        leader(3),
+        validators
+            .iter()
+            .map(|key| (key, QuorumCertificate::Happy(qc_1.clone())))
+            .collect(),
+    );
+    assert_eq!(everyone.verify(3, &ctx), Ok(()));
+
+    // Compact: the AggQC carries a single QC however many new views
+    // carried one
+    assert!(
+        borsh::to_vec(&everyone).unwrap().len()
+            < 2 * borsh::to_vec(&qc_1).unwrap().len()
+    );
+
+    // Swapping two claimed views unbinds them from their signers
+    let mut swapped = mixed.clone();
+    let (low, high) = (0..3)
+        .flat_map(|i| (0..3).map(move |j| (i, j)))
+        .find(|&(i, j)| mixed.high_qc_views[i] < mixed.high_qc_views[j])
+        .unwrap();
+    swapped.high_qc_views.swap(low, high);
+    assert_eq!(
+        swapped.verify(4, &ctx),
+        Err(CertificateError::BadAggregateSignature)
+    );
+
+    // Replacing the high QC with a higher, valid one nobody claimed
+    let qc_3 =
+        qc(set, 3, BlockHash([3; 32]), &validators[..3], leader(4));
+    let mut forged = mixed.clone();
+    forged.high_qc = Some(qc_3.clone().into());
+    assert_eq!(
+        forged.verify(4, &ctx),
+        Err(CertificateError::StaleView {
+            expected: 2,
+            found: 3
+        })
+    );
+
+    // ... and additionally rewriting a signer's claim to match it
+    forged.high_qc_views[low] = 3;
+    assert_eq!(
+        forged.verify(4, &ctx),
+        Err(CertificateError::BadAggregateSignature)
+    );
+
+    // Forwarding a lower QC than some signer claimed, or none at all
+    let mut hidden = mixed.clone();
+    hidden.high_qc = Some(qc_1.clone().into());
+    assert_eq!(
+        hidden.verify(4, &ctx),
+        Err(CertificateError::MissingHighQc)
+    );
+    hidden.high_qc = None;
+    assert_eq!(
+        hidden.verify(4, &ctx),
+        Err(CertificateError::MissingHighQc)
+    );
+
+    // Fewer claimed views than signers does not panic and is invalid
+    let mut truncated = mixed.clone();
+    truncated.high_qc_views.pop();
+    assert_eq!(
+        truncated.verify(4, &ctx),
+        Err(CertificateError::SignerCountMismatch {
+            signers: mixed.signers.count(),
+            claims: mixed.signers.count() - 1
+        })
+    );
+
+    // New views only sign their view and the view of their high QC
+    let eta = NewView {
+        view: 4,
+        certificate: QuorumCertificate::Happy(qc_2.clone()),
+    };
+    assert_eq!(
+        eta.summary(),
+        NewViewSummary {
+            view: 4,
+            high_qc_view: 2
+        }
+    );
+    assert!(SignedMessage::new_view(eta, &DOMAIN, &validators[0])
+        .verify(&DOMAIN));
+
+    // Attributing the claimed views to a validator that did not sign
+    let mut reattributed = mixed.clone();
+    let absent = (0..4)
+        .find(|index| !mixed.signers.contains(*index))
+        .unwrap();
+    let present = mixed.signers.indices().next().unwrap();
+    let mut bitmap = SignerBitmap::new(4);
+    for index in mixed.signers.indices() {
+        bitmap.insert(if index == present { absent } else { index });
+    }
+    reattributed.signers = bitmap;
+    assert_eq!(
+        reattributed.verify(4, &ctx),
+        Err(CertificateError::BadAggregateSignature)
+    );
+
+    // Signer bitmaps must be sized for the validator set and may not
+    // name validators past its end
+    let mut oversized = mixed.clone();
+    oversized.signers = SignerBitmap::from_bytes(
+        [mixed.signers.as_bytes(), &[0]].concat(),
+    );
+    assert_eq!(
+        oversized.verify(4, &ctx),
+        Err(CertificateError::MalformedSignerBitmap {
+            expected: 1,
+            found: 2
+        })
+    );
+    let mut out_of_range = qc_1.clone();
+    out_of_range.signers = SignerBitmap::from_bytes(vec![
+        qc_1.signers.as_bytes()[0] | 1 << 4,
+    ]);
+    assert_eq!(
+        out_of_range.verify(&ctx),
+        Err(CertificateError::UnknownSigner { index: 4 })
+    );
+
+    // Empty AggQC does not panic and is invalid
+    let mut empty = mixed.clone();
+    empty.high_qc_views.clear();
+    empty.high_qc = None;
+    empty.signers = SignerBitmap::new(4);
+    assert_eq!(empty.find_high_qc(), None);
+    assert_eq!(
+        empty.verify(4, &ctx),
+        Err(CertificateError::InsufficientQuorum {
+            signers: 0,
+            threshold: 3
+        })
+    );
+
+    // Below quorum
+    let too_few = aggqc(
+        set,
+        4,
+        leader(4),
+        validators[..2]
+            .iter()
+            .map(|key| (key, genesis.clone()))
+            .collect(),
+    );
+    assert_eq!(
+        too_few.verify(4, &ctx),
+        Err(CertificateError::InsufficientQuorum {
+            signers: 2,
+            threshold: 3
+        })
+    );
+
+    // A high QC that is not itself a quorum, even though every new view
+    // is correctly signed
+    let weak_qc =
+        qc(set, 2, BlockHash([2; 32]), &validators[..2], leader(3));
+    let weak = aggqc(
+        set,
+        4,
+        leader(4),
+        vec![
+            (&validators[0], genesis.clone()),
+            (&validators[1], QuorumCertificate::Happy(qc_1.clone())),
+            (&validators[2], QuorumCertificate::Happy(weak_qc)),
+        ],
+    );
+    assert_eq!(
+        weak.verify(4, &ctx),
+        Err(CertificateError::InsufficientQuorum {
+            signers: 2,
+            threshold: 3
+        })
+    );
+
+    // A high QC that is not below the aggregated view
+    let future = aggqc(
+        set,
+        2,
+        leader(2),
+        vec![
+            (&validators[0], genesis.clone()),
+            (&validators[1], genesis.clone()),
+            (&validators[2], QuorumCertificate::Happy(qc_2)),
+        ],
+    );
+    assert_eq!(
+        future.verify(2, &ctx),
+        Err(CertificateError::StaleView {
+            expected: 1,
+            found: 2
+        })
+    );
+
+    // Produced by a validator that is not the leader of the view
+    let usurped = aggqc(
+        set,
+        4,
+        not_leader(4),
+        vec![
+            (&validators[0], genesis.clone()),
+            (&validators[1], genesis.clone()),
+            (&validators[2], genesis.clone()),
+        ],
+    );
+    assert_eq!(
+        usurped.verify(4, &ctx),
+        Err(CertificateError::UnexpectedProducer(Box::new(
+            not_leader(4).public_key()
+        )))
+    );
+    let usurped_qc =
+        qc(set, 1, BlockHash([1; 32]), &validators[..3], not_leader(2));
+    assert_eq!(
+        usurped_qc.verify(&ctx),
+        Err(CertificateError::UnexpectedProducer(Box::new(
+            not_leader(2).public_key()
+        )))
+    );
+
+    // Producer signature over something other than the aggregated
+    // signature
+    let mut misattributed = mixed.clone();
+    misattributed.signature = qc_1.signature;
+    assert_eq!(
+        misattributed.verify(4, &ctx),
+        Err(CertificateError::BadProducerSignature)
+    );
+    let mut misattributed_qc = qc_1.clone();
+    misattributed_qc.signature = mixed.signature;
+    assert_eq!(
+        misattributed_qc.verify(&ctx),
+        Err(CertificateError::BadProducerSignature)
+    );
+
+    // Producer signature over the same aggregated signature, but made
+    // under another view
+    let mut rebound_qc = qc_1.clone();
+    rebound_qc.signature = leader(2)
+        .sign_request(
+            &SignRequest::Qc {
+                view: 5,
+                producer: qc_1.producer,
+                aggregated_signature: qc_1.aggregated_signature,
+            },
+            &DOMAIN,
+        )
+        .unwrap();
+    assert_eq!(
+        rebound_qc.verify(&ctx),
+        Err(CertificateError::BadProducerSignature)
+    );
+    let mut rebound = mixed.clone();
+    rebound.signature = leader(4)
+        .sign_request(
+            &SignRequest::AggQc {
+                view: 8,
+                producer: mixed.producer,
+                aggregated_signature: mixed.aggregated_signature,
+            },
+            &DOMAIN,
+        )
+        .unwrap();
+    assert_eq!(
+        rebound.verify(4, &ctx),
+        Err(CertificateError::BadProducerSignature)
+    );
+
+    // Bitmaps round trip through their encoding and expand back to the
+    // signers in canonical order, in n/8 bytes
+    let large =
+        ValidatorSet::new((0..100).map(|_| {
+            PrivateKey::from_seed(rand::random()).public_key()
+        }));
+    let signers: Vec<&PublicKey> = large.iter().step_by(3).collect();
+    let bitmap = SignerBitmap::from_signers(
+        &large,
+        signers.iter().rev().copied(),
+    )
+    .unwrap();
+    assert_eq!(bitmap.as_bytes().len(), 13);
+    assert_eq!(bitmap.count(), signers.len());
+    let decoded = SignerBitmap::from_bytes(bitmap.as_bytes().to_vec());
+    assert_eq!(decoded, bitmap);
+    let expanded: Vec<&PublicKey> = decoded
+        .indices()
+        .map(|index| large.get(index).unwrap())
+        .collect();
+    assert_eq!(expanded, signers);
+    assert!(SignerBitmap::from_signers(&large, set.iter()).is_none());
+
+    // Votes folded in one at a time form the same QC as aggregating
+    // them all at the end. Repeated votes are not counted twice and
+    // votes for another block are aggregated separately.
+    let vote = Vote {
+        view: 1,
+        blockhash: BlockHash([1; 32]),
+    };
+    let other = Vote {
+        view: 1,
+        blockhash: BlockHash([9; 32]),
+    };
+    let mut aggregator = VoteAggregator::new(set.clone(), DOMAIN);
+    for key in &validators[..2] {
+        let signature =
+            SignedMessage::vote(vote.clone(), &DOMAIN, key).signature;
+        let signer = key.public_key();
+        assert!(!aggregator.insert(vote.clone(), &signer, &signature));
+        assert!(!aggregator.insert(vote.clone(), &signer, &signature));
+    }
+    let equivocation =
+        SignedMessage::vote(other.clone(), &DOMAIN, &validators[2]);
+    assert!(!aggregator.insert(
+        other.clone(),
+        &equivocation.transmitter,
+        &equivocation.signature
+    ));
+    assert_eq!(aggregator.get(&vote).unwrap().count, 2);
+    assert!(!aggregator.is_ready(&vote));
+    assert!(aggregator
+        .certificate(&vote, leader(2))
+        .is_none());
+    let third =
+        SignedMessage::vote(vote.clone(), &DOMAIN, &validators[2]);
+    assert!(aggregator.insert(
+        vote.clone(),
+        &third.transmitter,
+        &third.signature
+    ));
+    let QuorumCertificate::Happy(incremental) = aggregator
+        .certificate(&vote, leader(2))
+        .unwrap()
+        .unwrap()
+    else {
+        unreachable!()
+    };
+    assert_eq!(incremental.verify(&ctx), Ok(()));
+    assert_eq!(incremental, qc_1);
+
+    // Verified certificates are cached, including the high QC nested in
+    // an AggQC, and only ever the valid ones
+    let cache = CertificateCache::new(8);
+    let cached = ctx.with_cache(&cache);
+    assert_eq!(mixed.verify(4, &cached), Ok(()));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(mixed.verify(4, &cached), Ok(()));
+    assert_eq!(qc_1.verify(&cached), Ok(()));
+    assert_eq!(
+        mixed
+            .find_high_qc()
+            .unwrap()
+            .verify(&cached),
+        Ok(())
+    );
+    assert_eq!(cache.len(), 3);
+    assert!(mixed.verify(5, &cached).is_err());
+    assert_eq!(
+        misattributed_qc.verify(&cached),
+        Err(CertificateError::BadProducerSignature)
+    );
+    // The forged AggQC is rejected, but the valid QC it carries is
+    // cached
+    assert_eq!(
+        forged.verify(4, &cached),
+        Err(CertificateError::BadAggregateSignature)
+    );
+    assert_eq!(cache.len(), 4);
+    assert_eq!(
+        forged.verify(4, &cached),
+        Err(CertificateError::BadAggregateSignature)
+    );
+    assert_eq!(cache.len(), 4);
+
+    // The oldest certificate is evicted first and simply verified again
+    let small = CertificateCache::new(1);
+    let cached = ctx.with_cache(&small);
+    assert_eq!(qc_1.verify(&cached), Ok(()));
+    assert_eq!(mixed.verify(4, &cached), Ok(()));
+    assert_eq!(small.len(), 1);
+    assert_eq!(qc_1.verify(&cached), Ok(()));
+    assert_eq!(small.len(), 1);
+
+    // Signatures are bound to their chain, protocol version and kind
+    // of message
+    let signed =
+        SignedMessage::vote(vote.clone(), &DOMAIN, &validators[0]);
+    assert!(signed.verify(&DOMAIN));
+    assert!(!signed.verify(&SigningDomain::new(1)));
+    assert!(!signed.verify(&SigningDomain {
+        chain_id: 0,
+        version: 2
+    }));
+    for kind in [
+        SignatureKind::Block,
+        SignatureKind::NewView,
+        SignatureKind::Handshake,
+        SignatureKind::QcProducer,
+        SignatureKind::AggQcProducer,
+        SignatureKind::Transaction,
+    ] {
+        assert!(!signed.transmitter.verify(
+            &DOMAIN.signing_bytes(kind, &vote),
+            &signed.signature
+        ));
+    }
+    let replayed = SignedMessage {
+        message_type: MessageType::NewView(NewView {
+            view: vote.view,
+            certificate: genesis.clone(),
+        }),
+        ..signed
+    };
+    assert!(!replayed.verify(&DOMAIN));
+
+    // Certificates produced for one chain are rejected on another
+    let other_epochs = EpochSchedule::new(set.clone());
+    let other_chain =
+        VerifyContext::new(&other_epochs, SigningDomain::new(1));
+    assert_eq!(
+        qc_1.verify(&other_chain),
+        Err(CertificateError::BadProducerSignature)
+    );
+    assert_eq!(
+        mixed.verify(4, &other_chain),
+        Err(CertificateError::BadProducerSignature)
+    );
+
+    println!("all aggQC vectors passed");
+}
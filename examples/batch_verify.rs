@@ -0,0 +1,117 @@
+use std::time::Instant;
+
+use pfhs::{
+    batch_verifier::BatchVerifier,
+    block::BlockHash,
+    cluster::DOMAIN,
+    crypto::{PrivateKey, Signer},
+    domain::SigningDomain,
+    message::{MessageType, SignedMessage, Vote},
+};
+
+fn vote(
+    view: u64,
+    blockhash: BlockHash,
+    key: &PrivateKey,
+) -> SignedMessage {
+    SignedMessage::vote(Vote { view, blockhash }, &DOMAIN, key)
+}
+
+fn main() {
+    let keys: Vec<PrivateKey> = (0..64)
+        .map(|_| PrivateKey::from_seed(rand::random()))
+        .collect();
+
+    // Empty batch
+    let mut batch = BatchVerifier::new(DOMAIN);
+    let verified = batch.verify();
+    assert!(verified.valid.is_empty() && verified.invalid.is_empty());
+
+    // Every validator votes for the same block, plus a few distinct
+    // votes
+    let mut messages: Vec<SignedMessage> = keys
+        .iter()
+        .map(|key| vote(1, BlockHash([1; 32]), key))
+        .collect();
+    messages.extend(
+        keys.iter()
+            .take(8)
+            .enumerate()
+            .map(|(i, key)| vote(2, BlockHash([i as u8; 32]), key)),
+    );
+    for message in &messages {
+        batch.push(message.clone());
+    }
+    assert_eq!(batch.len(), 72);
+    let verified = batch.verify();
+    assert!(batch.is_empty());
+    assert_eq!(verified.valid.len(), 72);
+    assert!(verified.invalid.is_empty());
+
+    // Tampered payload, signature by someone else and a swapped
+    // signature are each isolated, the rest is kept in order
+    let mut tampered = messages.clone();
+    let MessageType::Vote(vote_5) = &mut tampered[5].message_type
+    else {
+        unreachable!()
+    };
+    vote_5.view = 7;
+    tampered[20].transmitter = keys[21].public_key();
+    tampered[70].signature = messages[71].signature;
+    for message in &tampered {
+        batch.push(message.clone());
+    }
+    let verified = batch.verify();
+    assert_eq!(verified.invalid.len(), 3);
+    assert_eq!(verified.valid.len(), 69);
+    let invalid: Vec<_> = verified
+        .invalid
+        .iter()
+        .map(|message| message.signature)
+        .collect();
+    assert_eq!(
+        invalid,
+        [
+            tampered[5].signature,
+            tampered[20].signature,
+            tampered[70].signature
+        ]
+    );
+    assert!(verified
+        .valid
+        .iter()
+        .all(|message| message.verify(&DOMAIN)));
+
+    // A single bad message
+    batch.push(tampered[5].clone());
+    let verified = batch.verify();
+    assert_eq!(verified.invalid.len(), 1);
+
+    // Valid signatures for another chain are all rejected
+    let mut other_chain = BatchVerifier::new(SigningDomain::new(1));
+    for message in &messages {
+        other_chain.push(message.clone());
+    }
+    let verified = other_chain.verify();
+    assert!(verified.valid.is_empty());
+    assert_eq!(verified.invalid.len(), messages.len());
+
+    // Compare with one check per message
+    let start = Instant::now();
+    assert!(messages
+        .iter()
+        .all(|message| message.verify(&DOMAIN)));
+    let individually = start.elapsed();
+    let start = Instant::now();
+    for message in &messages {
+        batch.push(message.clone());
+    }
+    assert_eq!(batch.verify().valid.len(), messages.len());
+    let batched = start.elapsed();
+    println!(
+        "{} messages: {individually:?} one by one, {batched:?} batched",
+        messages.len()
+    );
+
+    println!("all batch verification vectors passed");
+}
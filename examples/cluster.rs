@@ -1,9 +1,17 @@
 use std::thread::JoinHandle;
 
-use pfhs::cluster::setup_cluster;
+use pfhs::cluster::{setup_cluster, setup_seeded_cluster};
 
 fn main() {
-    let endpoints = setup_cluster(1);
+    // Replay a previous run with PFHS_SEED=<printed cluster seed>
+    let endpoints = match std::env::var("PFHS_SEED") {
+        Ok(seed) => setup_seeded_cluster(
+            4,
+            seed.parse()
+                .expect("PFHS_SEED is a u64"),
+        ),
+        Err(_) => setup_cluster(4),
+    };
 
     let handles: Vec<JoinHandle<()>> = endpoints
         .into_iter()
@@ -0,0 +1,74 @@
+use pfhs::cluster::{
+    self, assert_agreement, random_validator_set, setup_cluster,
+};
+
+const VIEWS: u64 = 20;
+
+fn main() {
+    // Quorum math holds for every size, not only n = 3f+1
+    for n in 1..=40 {
+        let set = random_validator_set(n);
+        let f = set.fault_tolerance();
+        let q = set.quorum_threshold();
+        assert!(n > 3 * f, "n = {n} tolerates at most (n-1)/3 faults");
+        // Safety: two quorums share an honest validator
+        assert!(2 * q > n + f, "n = {n}: quorums intersect in <= f");
+        // Liveness: honest validators alone form a quorum
+        assert!(
+            q <= n - f,
+            "n = {n}: honest validators cannot progress"
+        );
+        assert!(
+            set.is_supermajority(q) && !set.is_supermajority(q - 1)
+        );
+    }
+    let six = random_validator_set(6);
+    assert_eq!((six.fault_tolerance(), six.quorum_threshold()), (1, 4));
+
+    // Fault-free clusters of non-3f+1 sizes make progress and agree
+    for n in [5, 6, 10] {
+        let endpoints = cluster::run(setup_cluster(n), VIEWS);
+
+        // Liveness: everyone committed something
+        for endpoint in &endpoints {
+            assert!(
+                !endpoint.committed().is_empty(),
+                "n = {n} stalled"
+            );
+        }
+
+        // Safety: committed chains are prefixes of one another
+        let longest = assert_agreement(&endpoints);
+        println!(
+            "n = {n}: committed {} blocks consistently",
+            longest.len()
+        );
+    }
+
+    // With f validators crashed, the n - f others still form quorums,
+    // counting the vote of each primary for its own proposal
+    for n in [4, 5, 6, 10] {
+        let mut endpoints = setup_cluster(n);
+        let f = endpoints[0]
+            .epochs()
+            .validator_set(0)
+            .fault_tolerance();
+        // Crashed endpoints are kept so their channels stay open
+        let live = endpoints.split_off(f);
+        let crashed = endpoints;
+        let endpoints = cluster::run(live, VIEWS);
+
+        let longest = assert_agreement(&endpoints);
+        for endpoint in &endpoints {
+            assert!(
+                !endpoint.committed().is_empty(),
+                "n = {n} with {f} crashed stalled"
+            );
+        }
+        println!(
+            "n = {n} with {f} crashed: committed {} blocks consistently",
+            longest.len()
+        );
+        drop(crashed);
+    }
+}
@@ -0,0 +1,44 @@
+use pfhs::{
+    cluster::{derive_key, setup_seeded_cluster},
+    crypto::{PublicKey, Signer},
+    domain::SigningDomain,
+    transaction::Transaction,
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+fn keys(seed: u64) -> Vec<PublicKey> {
+    setup_seeded_cluster(4, seed)
+        .iter()
+        .map(|endpoint| endpoint.public_key())
+        .collect()
+}
+
+fn main() {
+    // The same seed always gives the same cluster
+    assert_eq!(keys(7), keys(7));
+    assert_ne!(keys(7), keys(8));
+    assert_eq!(keys(7)[2], derive_key(7, 2).public_key());
+    assert_ne!(
+        derive_key(7, 2).public_key(),
+        derive_key(7, 3).public_key()
+    );
+
+    // ... and the same stream of transactions
+    let transactions = |seed: u64| {
+        let domain = SigningDomain::new(0);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let transactions = vec![
+            Transaction::new_valid_with_rng(&domain, &mut rng),
+            Transaction::new_invalid_with_rng(&mut rng),
+            Transaction::new_valid_with_rng(&domain, &mut rng),
+        ];
+        assert!(transactions[0].verify(&domain));
+        assert!(!transactions[1].verify(&domain));
+        assert!(!transactions[0].verify(&SigningDomain::new(1)));
+        borsh::to_vec(&transactions).unwrap()
+    };
+    assert_eq!(transactions(7), transactions(7));
+    assert_ne!(transactions(7), transactions(8));
+
+    println!("all determinism vectors passed");
+}
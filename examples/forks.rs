@@ -0,0 +1,131 @@
+use indexmap::IndexSet;
+use pfhs::{
+    block::{Block, BlockHash},
+    block_tree::BlockTree,
+    certificates::QuorumCertificate,
+    crypto::{PrivateKey, Signer},
+    domain::SigningDomain,
+    message::Vote,
+    transaction::Transaction,
+    validator_set::ValidatorSet,
+};
+
+/// Inserts a block at `view` extending `parent`, whose certificate
+/// certifies the block `certified`, and returns its hash.
+/// Signatures are not valid; the tree only inspects structure. A random
+/// transaction keeps otherwise identical blocks distinct.
+fn insert(
+    tree: &mut BlockTree,
+    key: &PrivateKey,
+    view: u64,
+    parent: BlockHash,
+    certified: BlockHash,
+) -> BlockHash {
+    let certificate = QuorumCertificate::from_votes(
+        Vote {
+            view: view - 1,
+            blockhash: certified,
+        },
+        &[key.sign(certified.as_bytes())],
+        IndexSet::new(),
+        &ValidatorSet::new([]),
+        &SigningDomain::new(0),
+        key,
+    )
+    .unwrap();
+    let block = Block {
+        transactions: vec![Transaction::new_valid(
+            &SigningDomain::new(0),
+        )],
+        certificate,
+        last_blockhash: parent,
+        view,
+    };
+    let blockhash = block.hash();
+    assert!(tree.insert(block, key.public_key()));
+    blockhash
+}
+
+fn main() {
+    let key = PrivateKey::from_seed(rand::random());
+    let genesis = BlockHash::ZERO;
+
+    // Linear chain 1 <- 2 <- 3 commits 1
+    let mut tree = BlockTree::new(genesis);
+    let one = insert(&mut tree, &key, 1, genesis, genesis);
+    let two = insert(&mut tree, &key, 2, one, one);
+    let three = insert(&mut tree, &key, 3, two, two);
+    assert_eq!(tree.commit_candidate(&three), Some(one));
+    let committed = tree.commit(&one);
+    assert_eq!(committed.len(), 1);
+    assert_eq!(tree.root(), one);
+    assert_eq!(tree.len(), 2);
+
+    // Gap: 1 <- 3 <- 4 does not commit 1 because 3 is not the direct
+    // child of 1
+    let mut tree = BlockTree::new(genesis);
+    let one = insert(&mut tree, &key, 1, genesis, genesis);
+    let three = insert(&mut tree, &key, 3, one, one);
+    let four = insert(&mut tree, &key, 4, three, three);
+    assert_eq!(tree.commit_candidate(&four), None);
+
+    // Fork: 1 <- 2 and 1 <- 3 <- 4 <- 5. Block 4 does not commit
+    // anything (3 skipped view 2), but 5 commits 3 (and with it 1),
+    // never the abandoned 2, which is pruned.
+    let mut tree = BlockTree::new(genesis);
+    let one = insert(&mut tree, &key, 1, genesis, genesis);
+    let two = insert(&mut tree, &key, 2, one, one);
+    let three = insert(&mut tree, &key, 3, one, one);
+    let four = insert(&mut tree, &key, 4, three, three);
+    assert_eq!(tree.commit_candidate(&four), None);
+    let five = insert(&mut tree, &key, 5, four, four);
+    assert_eq!(tree.commit_candidate(&five), Some(three));
+    let committed: Vec<BlockHash> = tree
+        .commit(&three)
+        .into_iter()
+        .map(|node| node.blockhash)
+        .collect();
+    assert_eq!(committed, vec![one, three]);
+    assert!(tree.get(&two).is_none());
+    assert_eq!(tree.at_view(2).count(), 0);
+    assert_eq!(
+        tree.highest()
+            .map(|node| node.blockhash),
+        Some(five)
+    );
+
+    // Equivocation: two blocks in view 2 on sibling branches. Only the
+    // branch with the certified direct child commits; the other is
+    // pruned along with its descendants.
+    let mut tree = BlockTree::new(genesis);
+    let one = insert(&mut tree, &key, 1, genesis, genesis);
+    let two_a = insert(&mut tree, &key, 2, one, one);
+    let two_b = insert(&mut tree, &key, 2, one, one);
+    let three_b = insert(&mut tree, &key, 3, two_b, two_b);
+    assert_eq!(tree.at_view(2).count(), 2);
+    let three_a = insert(&mut tree, &key, 3, two_a, two_a);
+    let four_a = insert(&mut tree, &key, 4, three_a, three_a);
+    assert_eq!(tree.commit_candidate(&four_a), Some(two_a));
+    tree.commit(&two_a);
+    assert!(tree.get(&two_b).is_none());
+    assert!(tree.get(&three_b).is_none());
+    assert!(tree.get(&three_a).is_some());
+
+    // Uncertified link: 3 extends 2 but its certificate certifies 1,
+    // so 2 is not certified and nothing commits
+    let mut tree = BlockTree::new(genesis);
+    let one = insert(&mut tree, &key, 1, genesis, genesis);
+    let two = insert(&mut tree, &key, 2, one, one);
+    let three = insert(&mut tree, &key, 3, two, one);
+    let four = insert(&mut tree, &key, 4, three, three);
+    assert_eq!(tree.commit_candidate(&four), None);
+
+    // Missing ancestor: the certified child is unknown
+    let mut tree = BlockTree::new(genesis);
+    insert(&mut tree, &key, 1, genesis, genesis);
+    let unknown = BlockHash([1; 32]);
+    let three = insert(&mut tree, &key, 3, unknown, unknown);
+    assert_eq!(tree.commit_candidate(&three), None);
+
+    println!("all fork scenarios passed");
+}
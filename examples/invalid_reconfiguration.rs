@@ -0,0 +1,55 @@
+use pfhs::{
+    block::{Block, BlockHash},
+    certificates::QuorumCertificate,
+    cluster::{self, assert_agreement, setup_cluster, DOMAIN},
+    transaction::Transaction,
+    validator_set::ValidatorSet,
+};
+
+/// Long enough for an epoch scheduled by a block committed early on to
+/// start
+const VIEWS: u64 = 30;
+
+fn main() {
+    // A reconfiguration to no validators is invalid, as no leader could
+    // be scheduled for its epoch
+    let empty = Transaction::reconfiguration([]);
+    assert!(!empty.verify(&DOMAIN));
+    assert!(ValidatorSet::from_registrations([]).is_none());
+    let block = Block {
+        transactions: vec![
+            Transaction::new_valid(&DOMAIN),
+            empty.clone(),
+        ],
+        certificate: QuorumCertificate::Genesis,
+        last_blockhash: BlockHash::ZERO,
+        view: 1,
+    };
+    assert!(!block.verify_transactions(&DOMAIN));
+
+    // An honest node refuses to propose it. The first node proposes it
+    // anyway when it becomes primary, as a byzantine leader would.
+    // Nobody votes for that block, so the view times out and the next
+    // primary carries on from an AggQC over the new views.
+    let mut endpoints = setup_cluster(4);
+    assert!(!endpoints[1].submit_transaction(empty.clone()));
+    assert!(endpoints[1]
+        .submit_transaction(Transaction::new_valid(&DOMAIN)));
+    endpoints[0].submit_unverified_transaction(empty);
+    let endpoints = cluster::run(endpoints, VIEWS);
+
+    // Liveness and agreement among the honest nodes. The byzantine
+    // node finishes its view early, times out waiting for the next
+    // block and so misses it.
+    let honest = &endpoints[1..];
+    let longest = assert_agreement(honest);
+    for endpoint in honest {
+        assert!(
+            endpoint.committed().len() + 5 >= longest.len(),
+            "every node keeps committing"
+        );
+    }
+    assert!(longest.len() > VIEWS as usize / 2);
+
+    println!("all invalid reconfiguration vectors passed");
+}
@@ -0,0 +1,147 @@
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use pfhs::{
+    cluster::setup_cluster_from_keystore,
+    crypto::Signer,
+    keystore::{self, KeystoreError},
+};
+
+fn main() {
+    let directory = std::env::temp_dir()
+        .join(format!("pfhs-keystore-{}", rand::random::<u64>()));
+    fs::create_dir(&directory).unwrap();
+
+    // Plain key files round trip and are only readable by their owner
+    let plain = directory.join("plain.key");
+    let key = keystore::generate(&plain, None).unwrap();
+    let mode = fs::metadata(&plain)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let loaded = keystore::load(&plain, None).unwrap();
+    assert_eq!(loaded.public_key(), key.public_key());
+    let identity =
+        keystore::load_identity(&plain, "alice", None).unwrap();
+    assert_eq!(identity.public_key, key.public_key());
+    assert_eq!(
+        keystore::export_public_key(&plain).unwrap(),
+        key.public_key().to_string()
+    );
+
+    // Existing keys are never overwritten
+    assert!(matches!(
+        keystore::generate(&plain, None),
+        Err(KeystoreError::Io(err))
+            if err.kind() == std::io::ErrorKind::AlreadyExists
+    ));
+    assert_eq!(
+        keystore::load(&plain, None)
+            .unwrap()
+            .public_key(),
+        key.public_key()
+    );
+
+    // Key files accessible to others are refused
+    fs::set_permissions(&plain, fs::Permissions::from_mode(0o644))
+        .unwrap();
+    assert!(matches!(
+        keystore::load(&plain, None),
+        Err(KeystoreError::InsecurePermissions { mode: 0o644 })
+    ));
+    fs::set_permissions(&plain, fs::Permissions::from_mode(0o600))
+        .unwrap();
+
+    // Encrypted key files need the right passphrase, but the public key
+    // can be exported without it
+    let encrypted = directory.join("encrypted.key");
+    let key = keystore::generate(&encrypted, Some("hunter2")).unwrap();
+    assert_eq!(
+        keystore::load(&encrypted, Some("hunter2"))
+            .unwrap()
+            .public_key(),
+        key.public_key()
+    );
+    assert!(matches!(
+        keystore::load(&encrypted, Some("hunter3")),
+        Err(KeystoreError::WrongPassphrase)
+    ));
+    assert!(matches!(
+        keystore::load(&encrypted, None),
+        Err(KeystoreError::PassphraseRequired)
+    ));
+    assert_eq!(
+        keystore::export_public_key(&encrypted).unwrap(),
+        key.public_key().to_string()
+    );
+
+    // Tampering is detected
+    let bytes = fs::read(&encrypted).unwrap();
+    let tampered = directory.join("tampered.key");
+    let write = |path: &std::path::Path, bytes: &[u8]| {
+        fs::write(path, bytes).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .unwrap();
+    };
+    let mut flipped = bytes.clone();
+    // Last byte of the ciphertext, just before the tag
+    flipped[bytes.len() - 17] ^= 1;
+    write(&tampered, &flipped);
+    assert!(matches!(
+        keystore::load(&tampered, Some("hunter2")),
+        Err(KeystoreError::WrongPassphrase)
+    ));
+    write(&tampered, &bytes[..bytes.len() - 1]);
+    assert!(matches!(
+        keystore::load(&tampered, Some("hunter2")),
+        Err(KeystoreError::Malformed)
+    ));
+    write(&tampered, &[&[2], &bytes[1..]].concat());
+    assert!(matches!(
+        keystore::load(&tampered, Some("hunter2")),
+        Err(KeystoreError::UnsupportedVersion(2))
+    ));
+
+    // The iteration count is authenticated, and absurd counts are
+    // refused before deriving any key. It precedes the nonce, the
+    // ciphertext and the tag.
+    let iterations = bytes.len() - 64;
+    let with_iterations = |count: u32| {
+        let mut bytes = bytes.clone();
+        bytes[iterations..iterations + 4]
+            .copy_from_slice(&count.to_le_bytes());
+        bytes
+    };
+    write(&tampered, &with_iterations(100_001));
+    assert!(matches!(
+        keystore::load(&tampered, Some("hunter2")),
+        Err(KeystoreError::WrongPassphrase)
+    ));
+    for count in [0, u32::MAX] {
+        write(&tampered, &with_iterations(count));
+        assert!(matches!(
+            keystore::load(&tampered, Some("hunter2")),
+            Err(KeystoreError::UnsupportedIterations(found))
+                if found == count
+        ));
+    }
+
+    // Validators keep their identities across restarts
+    let cluster = directory.join("cluster");
+    fs::create_dir(&cluster).unwrap();
+    let identities = |endpoints: Vec<pfhs::endpoint::Endpoint>| {
+        endpoints
+            .iter()
+            .map(|endpoint| endpoint.public_key())
+            .collect::<Vec<_>>()
+    };
+    let first =
+        identities(setup_cluster_from_keystore(&cluster, 4).unwrap());
+    let second =
+        identities(setup_cluster_from_keystore(&cluster, 4).unwrap());
+    assert_eq!(first, second);
+    assert_eq!(fs::read_dir(&cluster).unwrap().count(), 4);
+
+    fs::remove_dir_all(&directory).unwrap();
+    println!("all keystore vectors passed");
+}
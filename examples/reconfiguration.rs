@@ -0,0 +1,159 @@
+use pfhs::{
+    block::BlockHash,
+    certificates::{QuorumCertificate, VerifyContext, QC},
+    cluster::{
+        self, assert_agreement, derive_key,
+        setup_seeded_cluster_with_standby, DOMAIN,
+    },
+    crypto::{PrivateKey, Registration, Signer},
+    epoch::{EpochSchedule, EPOCH_ACTIVATION_DELAY},
+    message::{SignedMessage, Vote},
+    transaction::Transaction,
+    validator_set::ValidatorSet,
+    vote_aggregator::VoteAggregator,
+};
+
+/// Long enough for the reconfiguration to commit and for its epoch to
+/// run for a while
+const VIEWS: u64 = 40;
+
+fn main() {
+    // A reconfiguration proposed in view 5 whose commit timeouts held
+    // up until the block for view 23 takes effect 10 views after that
+    // block, rather than from view 15 on, which already ran
+    let keys: Vec<PrivateKey> = (0..5)
+        .map(|_| PrivateKey::from_seed(rand::random()))
+        .collect();
+    let genesis_set =
+        ValidatorSet::new(keys[..4].iter().map(Signer::public_key));
+    let next_set =
+        ValidatorSet::new(keys[1..].iter().map(Signer::public_key));
+    let mut epochs = EpochSchedule::new(genesis_set.clone());
+    let (proposed_view, commit_view) = (5, 23);
+    assert!(commit_view > proposed_view + EPOCH_ACTIVATION_DELAY);
+    let epoch = epochs.schedule(commit_view, next_set.clone());
+    assert_eq!(epoch.start_view, commit_view + EPOCH_ACTIVATION_DELAY);
+    for view in 0..=commit_view {
+        assert_eq!(epochs.validator_set(view), &genesis_set);
+    }
+    assert_eq!(
+        epochs.validator_set(commit_view + EPOCH_ACTIVATION_DELAY),
+        &next_set
+    );
+
+    // Four genesis validators and one standby node. The second
+    // validator proposes a reconfiguration that removes the first one
+    // and admits the standby node.
+    let seed = rand::random();
+    println!("cluster seed is {seed}");
+    let mut endpoints = setup_seeded_cluster_with_standby(4, 1, seed);
+    let registrations: Vec<Registration> = endpoints
+        .iter()
+        .map(|endpoint| {
+            endpoint
+                .registration()
+                .expect("local keys always sign")
+        })
+        .collect();
+    let (removed, incoming) =
+        (derive_key(seed, 0), derive_key(seed, 4));
+    let next_set = ValidatorSet::from_registrations(
+        registrations[1..].iter().copied(),
+    )
+    .unwrap();
+    assert!(endpoints[1].submit_transaction(
+        Transaction::reconfiguration(
+            registrations[1..].iter().copied(),
+        )
+    ));
+
+    let endpoints = cluster::run(endpoints, VIEWS);
+
+    // Every node, including the removed and the incoming one, switches
+    // to the new set at the same view
+    let switch = endpoints[1]
+        .epochs()
+        .epoch_for_view(VIEWS)
+        .clone();
+    assert_eq!(switch.number, 1);
+    assert_eq!(switch.validator_set, next_set);
+    assert!(switch.start_view + 10 < VIEWS, "the new epoch ran");
+    for endpoint in &endpoints {
+        let epoch = endpoint.epochs().epoch_for_view(VIEWS);
+        assert_eq!(epoch.number, switch.number);
+        assert_eq!(epoch.start_view, switch.start_view);
+        assert_eq!(epoch.validator_set, next_set);
+    }
+
+    // Committed chains agree
+    assert_agreement(&endpoints);
+    let longest = endpoints
+        .iter()
+        .max_by_key(|endpoint| endpoint.committed().len())
+        .unwrap();
+
+    // The incoming validator's votes count towards the QCs of the new
+    // epoch, which verify against the new set only
+    let epochs = longest.epochs();
+    let ctx = VerifyContext::new(epochs, DOMAIN);
+    let genesis_epochs =
+        EpochSchedule::new(epochs.validator_set(0).clone());
+    let stale_ctx = VerifyContext::new(&genesis_epochs, DOMAIN);
+    let incoming_index = next_set
+        .index_of(&incoming.public_key())
+        .unwrap();
+    let qcs: Vec<&QC> = longest
+        .committed_blocks()
+        .iter()
+        .filter_map(|node| match &node.block.certificate {
+            QuorumCertificate::Happy(qc)
+                if qc.vote.view >= switch.start_view
+                    && qc.signers.contains(incoming_index) =>
+            {
+                Some(qc)
+            }
+            _ => None,
+        })
+        .collect();
+    assert!(!qcs.is_empty(), "the incoming validator voted");
+    for qc in qcs {
+        assert_eq!(qc.verify(&ctx), Ok(()));
+        assert!(qc.verify(&stale_ctx).is_err());
+    }
+
+    // The removed validator's votes count until the switch and are
+    // rejected from then on, unlike the incoming validator's
+    for (view, removed_counts) in
+        [(switch.start_view - 1, true), (switch.start_view, false)]
+    {
+        let vote = Vote {
+            view,
+            blockhash: BlockHash([1; 32]),
+        };
+        let mut votes = VoteAggregator::new(
+            epochs.validator_set(view).clone(),
+            DOMAIN,
+        );
+        for key in [&removed, &incoming] {
+            let signed =
+                SignedMessage::vote(vote.clone(), &DOMAIN, key);
+            votes.insert(
+                vote.clone(),
+                &key.public_key(),
+                &signed.signature,
+            );
+        }
+        let count = votes
+            .get(&vote)
+            .map_or(0, |votes| votes.count);
+        assert_eq!(count, 1, "exactly one of them is a validator");
+        assert_eq!(
+            epochs
+                .validator_set(view)
+                .contains(&removed.public_key()),
+            removed_counts
+        );
+    }
+
+    println!("all reconfiguration vectors passed");
+}
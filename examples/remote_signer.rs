@@ -0,0 +1,190 @@
+use std::{fs, os::unix::net::UnixListener, sync::Arc};
+
+use pfhs::{
+    block::BlockHash,
+    cluster::{
+        self, assert_agreement, setup_cluster_with_identities, DOMAIN,
+    },
+    crypto::{PrivateKey, Signer},
+    domain::{SignatureKind, SigningDomain},
+    endpoint::Identity,
+    message::{MessageType, SignedMessage, Vote},
+    remote_signer::{self, RemoteSigner},
+    signer::{ConsensusSigner, GuardedSigner, SignError, SignRequest},
+};
+
+const VIEWS: u64 = 20;
+
+fn vote(view: u64, blockhash: u8) -> SignRequest {
+    SignRequest::Vote(Vote {
+        view,
+        blockhash: BlockHash([blockhash; 32]),
+    })
+}
+
+/// Serves a guarded signer for `private_key` on a socket in `directory`
+/// and returns the socket path
+fn spawn_signer(
+    directory: &std::path::Path,
+    name: &str,
+    private_key: PrivateKey,
+) -> std::path::PathBuf {
+    let socket = directory.join(format!("{name}.sock"));
+    let listener = UnixListener::bind(&socket).unwrap();
+    let signer = GuardedSigner::new(private_key, DOMAIN);
+    std::thread::spawn(move || {
+        remote_signer::serve(listener, Arc::new(signer))
+    });
+    socket
+}
+
+fn main() {
+    let directory = std::env::temp_dir()
+        .join(format!("pfhs-signer-{}", rand::random::<u64>()));
+    fs::create_dir(&directory).unwrap();
+
+    // Signing the same thing twice is fine and gives the same signature
+    let key = PrivateKey::from_seed(rand::random());
+    let guarded = GuardedSigner::new(key.clone(), DOMAIN);
+    let first = guarded
+        .sign_request(&vote(3, 1), &DOMAIN)
+        .unwrap();
+    assert_eq!(guarded.sign_request(&vote(3, 1), &DOMAIN), Ok(first));
+    let signed = SignedMessage {
+        message_type: MessageType::Vote(Vote {
+            view: 3,
+            blockhash: BlockHash([1; 32]),
+        }),
+        transmitter: key.public_key(),
+        signature: first,
+    };
+    assert!(signed.verify(&DOMAIN));
+
+    // ... but nothing else for the same view, nor for an earlier one
+    assert_eq!(
+        guarded.sign_request(&vote(3, 2), &DOMAIN),
+        Err(SignError::Equivocation {
+            kind: SignatureKind::Vote,
+            view: 3
+        })
+    );
+    assert_eq!(
+        guarded.sign_request(&vote(2, 1), &DOMAIN),
+        Err(SignError::StaleView {
+            kind: SignatureKind::Vote,
+            view: 2,
+            latest: 3
+        })
+    );
+    assert!(guarded
+        .sign_request(&vote(4, 2), &DOMAIN)
+        .is_ok());
+
+    // Every kind of request has its own watermark
+    let aggregated_signature = first;
+    let qc = |view: u64, producer| SignRequest::Qc {
+        view,
+        producer,
+        aggregated_signature,
+    };
+    assert!(guarded
+        .sign_request(&qc(3, key.public_key()), &DOMAIN)
+        .is_ok());
+    assert!(guarded
+        .sign_request(&SignRequest::Handshake(BlockHash::ZERO), &DOMAIN)
+        .is_ok());
+
+    // Requests for another chain or another key are refused
+    assert_eq!(
+        guarded.sign_request(&vote(5, 1), &SigningDomain::new(1)),
+        Err(SignError::WrongDomain)
+    );
+    let other = PrivateKey::from_seed(rand::random()).public_key();
+    assert_eq!(
+        guarded.sign_request(&qc(5, other), &DOMAIN),
+        Err(SignError::WrongSigner)
+    );
+    assert!(guarded.registration().unwrap().verify());
+
+    // Watermarks survive a restart of the signer
+    let state = directory.join("signer.state");
+    let persistent =
+        GuardedSigner::with_state_file(key.clone(), DOMAIN, &state)
+            .unwrap();
+    assert!(persistent
+        .sign_request(&vote(7, 1), &DOMAIN)
+        .is_ok());
+    drop(persistent);
+    let restarted =
+        GuardedSigner::with_state_file(key.clone(), DOMAIN, &state)
+            .unwrap();
+    assert_eq!(
+        restarted.sign_request(&vote(7, 2), &DOMAIN),
+        Err(SignError::Equivocation {
+            kind: SignatureKind::Vote,
+            view: 7
+        })
+    );
+    assert!(restarted
+        .sign_request(&vote(7, 1), &DOMAIN)
+        .is_ok());
+
+    // Over a socket, signatures and refusals come back alike
+    let socket = spawn_signer(&directory, "single", key.clone());
+    let remote = RemoteSigner::connect(&socket).unwrap();
+    assert_eq!(remote.signer_public_key(), key.public_key());
+    let message = SignedMessage::new(
+        MessageType::Vote(Vote {
+            view: 1,
+            blockhash: BlockHash([1; 32]),
+        }),
+        &DOMAIN,
+        &remote,
+    )
+    .unwrap();
+    assert!(message.verify(&DOMAIN));
+    assert_eq!(
+        SignedMessage::new(
+            MessageType::Vote(Vote {
+                view: 1,
+                blockhash: BlockHash([9; 32])
+            }),
+            &DOMAIN,
+            &remote
+        )
+        .unwrap_err(),
+        SignError::Equivocation {
+            kind: SignatureKind::Vote,
+            view: 1
+        }
+    );
+    assert!(remote.registration().unwrap().verify());
+
+    // A cluster whose keys all live in signers makes progress and
+    // agrees
+    let identities = ["alice", "bob", "carol", "dave"]
+        .into_iter()
+        .map(|name| {
+            let socket = spawn_signer(
+                &directory,
+                name,
+                PrivateKey::from_seed(rand::random()),
+            );
+            Identity::remote(
+                name,
+                RemoteSigner::connect(socket).unwrap(),
+            )
+        })
+        .collect();
+    let endpoints = cluster::run(
+        setup_cluster_with_identities(identities, 4),
+        VIEWS,
+    );
+    assert_agreement(&endpoints);
+    for endpoint in &endpoints {
+        assert!(!endpoint.committed().is_empty());
+    }
+
+    fs::remove_dir_all(&directory).unwrap();
+    println!("all remote signer vectors passed");
+}
@@ -0,0 +1,485 @@
+use indexmap::IndexSet;
+use pfhs::{
+    block::BlockHash,
+    certificates::{
+        CertificateError, HighQC, QuorumCertificate, VerifyContext,
+    },
+    cluster::{
+        self, assert_agreement, random_validator_set,
+        setup_seeded_threshold_cluster, setup_threshold_cluster,
+        DOMAIN,
+    },
+    crypto::{PrivateKey, PublicKey, Registration, Signer},
+    domain::SigningDomain,
+    epoch::EpochSchedule,
+    genesis::{GenesisConfig, GenesisError},
+    message::{NewView, SignedMessage, Vote},
+    signer::{ConsensusSigner, SignRequest},
+    threshold::{
+        self, PartialAggregator, PartialSignature, ThresholdKeys,
+        ThresholdKeysError, ThresholdQC,
+    },
+    validator_set::ValidatorSet,
+};
+
+/// Views the threshold cluster runs for
+const VIEWS: u64 = 20;
+
+/// Threshold QC over `vote` combined from `partials`, produced by
+/// `producer`
+fn threshold_qc(
+    vote: &Vote,
+    partials: &[PartialSignature],
+    keys: &ThresholdKeys,
+    producer: &PrivateKey,
+) -> ThresholdQC {
+    let group_signature =
+        ThresholdQC::combine_partials(vote, partials, keys, &DOMAIN)
+            .unwrap();
+    ThresholdQC::from_group_signature(
+        vote.clone(),
+        group_signature,
+        &DOMAIN,
+        producer,
+    )
+    .unwrap()
+}
+
+fn main() {
+    let mut rng = rand::thread_rng();
+    let vote = Vote {
+        view: 5,
+        blockhash: BlockHash([7; 32]),
+    };
+
+    // n = 4, f = 1: any 3 partials make a QC
+    let producer = PrivateKey::from_seed(rand::random());
+    let set = random_validator_set(4);
+    let (keys, shares) = threshold::deal(&set, &mut rng);
+    assert_eq!(keys.threshold, 3);
+    let partials: Vec<PartialSignature> = shares
+        .iter()
+        .map(|share| share.sign_vote(&vote, &DOMAIN))
+        .collect();
+    for partial in &partials {
+        assert!(keys.verify_partial(&vote, partial, &DOMAIN));
+    }
+
+    // Every quorum combines into the same group signature
+    let quorums =
+        [[0, 1, 2], [0, 1, 3], [0, 2, 3], [1, 2, 3], [3, 1, 0]];
+    let certificates: Vec<ThresholdQC> = quorums
+        .iter()
+        .map(|quorum| {
+            let partials: Vec<PartialSignature> = quorum
+                .iter()
+                .map(|&i| partials[i])
+                .collect();
+            threshold_qc(&vote, &partials, &keys, &producer)
+        })
+        .collect();
+    for certificate in &certificates {
+        assert_eq!(certificate, &certificates[0]);
+        assert_eq!(
+            certificate
+                .verify_with_key(&keys.group_public_key, &DOMAIN),
+            Ok(())
+        );
+    }
+    let certificate = &certificates[0];
+
+    // Duplicated partials count once
+    assert_eq!(
+        ThresholdQC::combine_partials(
+            &vote,
+            &[partials[0], partials[1], partials[1]],
+            &keys,
+            &DOMAIN
+        ),
+        Err(CertificateError::InsufficientQuorum {
+            signers: 2,
+            threshold: 3
+        })
+    );
+
+    // A partial does not verify for another vote, another share or
+    // another domain
+    let other_vote = Vote {
+        view: 6,
+        blockhash: BlockHash([7; 32]),
+    };
+    let other_domain = SigningDomain::new(1);
+    assert!(!keys.verify_partial(&other_vote, &partials[0], &DOMAIN));
+    assert!(!keys.verify_partial(
+        &vote,
+        &PartialSignature {
+            index: 1,
+            ..partials[0]
+        },
+        &DOMAIN
+    ));
+    assert!(!keys.verify_partial(
+        &vote,
+        &PartialSignature {
+            index: 4,
+            ..partials[0]
+        },
+        &DOMAIN
+    ));
+    assert!(!keys.verify_partial(&vote, &partials[0], &other_domain));
+
+    // A QC does not verify for another vote, another domain or another
+    // group key
+    let tampered = ThresholdQC {
+        vote: other_vote.clone(),
+        ..certificate.clone()
+    };
+    assert_eq!(
+        tampered.verify_with_key(&keys.group_public_key, &DOMAIN),
+        Err(CertificateError::BadAggregateSignature)
+    );
+    assert_eq!(
+        certificate
+            .verify_with_key(&keys.group_public_key, &other_domain),
+        Err(CertificateError::BadAggregateSignature)
+    );
+    let (other_keys, _) = threshold::deal(&set, &mut rng);
+    assert_eq!(
+        certificate
+            .verify_with_key(&other_keys.group_public_key, &DOMAIN),
+        Err(CertificateError::BadAggregateSignature)
+    );
+
+    // Partials over something else are left out, so they neither count
+    // towards the threshold nor spoil the QC
+    let other_partial = shares[2].sign_vote(&other_vote, &DOMAIN);
+    assert_eq!(
+        ThresholdQC::combine_partials(
+            &vote,
+            &[partials[0], partials[1], other_partial],
+            &keys,
+            &DOMAIN
+        ),
+        Err(CertificateError::InsufficientQuorum {
+            signers: 2,
+            threshold: 3
+        })
+    );
+    assert_eq!(
+        ThresholdQC::combine_partials(
+            &vote,
+            &[other_partial, partials[0], partials[1], partials[3]],
+            &keys,
+            &DOMAIN
+        )
+        .as_ref(),
+        Ok(&certificate.group_signature)
+    );
+
+    // Partials from shares that do not exist are refused
+    assert_eq!(
+        ThresholdQC::combine_partials(
+            &vote,
+            &[
+                partials[0],
+                partials[1],
+                partials[2],
+                PartialSignature {
+                    index: 4,
+                    ..partials[3]
+                },
+            ],
+            &keys,
+            &DOMAIN
+        ),
+        Err(CertificateError::UnknownSigner { index: 4 })
+    );
+
+    // Certificates are the same size whatever the validator set
+    let size = borsh::to_vec(certificate)
+        .unwrap()
+        .len();
+    for validators in [1, 7, 10, 31, 100] {
+        let set = random_validator_set(validators);
+        let (keys, shares) = threshold::deal(&set, &mut rng);
+        assert_eq!(keys.threshold, set.quorum_threshold());
+
+        // One short of the threshold is not enough
+        let partials: Vec<PartialSignature> = shares
+            .iter()
+            .rev()
+            .take(keys.threshold)
+            .map(|share| share.sign_vote(&vote, &DOMAIN))
+            .collect();
+        assert_eq!(
+            ThresholdQC::combine_partials(
+                &vote,
+                &partials[1..],
+                &keys,
+                &DOMAIN
+            ),
+            Err(CertificateError::InsufficientQuorum {
+                signers: keys.threshold - 1,
+                threshold: keys.threshold
+            })
+        );
+
+        let certificate =
+            threshold_qc(&vote, &partials, &keys, &producer);
+        assert_eq!(
+            certificate
+                .verify_with_key(&keys.group_public_key, &DOMAIN),
+            Ok(())
+        );
+        assert_eq!(
+            borsh::to_vec(&certificate)
+                .unwrap()
+                .len(),
+            size
+        );
+    }
+
+    // In consensus, threshold QCs are verified against the group key of
+    // the epoch of the certified view, in epochs that have one
+    let private_keys: Vec<PrivateKey> = (0..4)
+        .map(|_| PrivateKey::from_seed(rand::random()))
+        .collect();
+    let set = ValidatorSet::from_registrations(
+        private_keys
+            .iter()
+            .map(Registration::new),
+    )
+    .unwrap();
+    let (keys, shares) = threshold::deal(&set, &mut rng);
+    let epochs = EpochSchedule::new(set.clone())
+        .with_threshold_keys(keys.clone())
+        .unwrap();
+    let ctx = VerifyContext::new(&epochs, DOMAIN);
+    let aggregate_only = EpochSchedule::new(set.clone());
+    let aggregate_ctx = VerifyContext::new(&aggregate_only, DOMAIN);
+    let misdealt = EpochSchedule::new(set.clone())
+        .with_threshold_keys(other_keys.clone())
+        .unwrap();
+    let partials: Vec<PartialSignature> = shares
+        .iter()
+        .map(|share| share.sign_vote(&vote, &DOMAIN))
+        .collect();
+    let key_of = |public_key: &PublicKey| {
+        private_keys
+            .iter()
+            .find(|key| key.public_key() == *public_key)
+            .unwrap()
+    };
+    let producer = key_of(set.leader(vote.view + 1));
+    let certificate = threshold_qc(&vote, &partials, &keys, producer);
+    assert_eq!(certificate.verify(&ctx), Ok(()));
+    assert_eq!(
+        certificate.verify(&aggregate_ctx),
+        Err(CertificateError::NoGroupKey { view: 5 })
+    );
+    assert_eq!(
+        certificate.verify(&VerifyContext::new(&misdealt, DOMAIN)),
+        Err(CertificateError::BadAggregateSignature)
+    );
+
+    // Like aggregate QCs, threshold QCs are produced by the primary the
+    // votes were sent to, which signs them for the certified view
+    let usurper = key_of(set.leader(vote.view));
+    let usurped = threshold_qc(&vote, &partials, &keys, usurper);
+    assert_eq!(
+        usurped.verify(&ctx),
+        Err(CertificateError::UnexpectedProducer(Box::new(
+            usurper.public_key()
+        )))
+    );
+    let mut rebound = certificate.clone();
+    rebound.signature = producer
+        .sign_request(
+            &SignRequest::Qc {
+                view: vote.view + 4,
+                producer: producer.public_key(),
+                aggregated_signature: certificate.group_signature,
+            },
+            &DOMAIN,
+        )
+        .unwrap();
+    assert_eq!(
+        rebound.verify(&ctx),
+        Err(CertificateError::BadProducerSignature)
+    );
+    let misattributed = ThresholdQC {
+        signature: usurped.signature,
+        ..certificate.clone()
+    };
+    assert_eq!(
+        misattributed.verify(&ctx),
+        Err(CertificateError::BadProducerSignature)
+    );
+
+    // Shares are only used with the keys they were dealt with
+    assert!(shares
+        .iter()
+        .all(|share| keys.holds(share)));
+    assert!(!shares
+        .iter()
+        .any(|share| other_keys.holds(share)));
+
+    // Keys are refused for a set they cannot serve, with a threshold
+    // below its quorum or without one share per validator, in genesis
+    // configs as well
+    let quorum = set.quorum_threshold();
+    let lowered = ThresholdKeys {
+        threshold: quorum - 1,
+        ..keys.clone()
+    };
+    let lowered_error = ThresholdKeysError::ThresholdBelowQuorum {
+        threshold: quorum - 1,
+        quorum,
+    };
+    assert_eq!(
+        EpochSchedule::new(set.clone())
+            .with_threshold_keys(lowered.clone())
+            .err(),
+        Some(lowered_error.clone())
+    );
+    let truncated = ThresholdKeys {
+        share_public_keys: keys.share_public_keys[1..].to_vec(),
+        ..keys.clone()
+    };
+    assert_eq!(
+        EpochSchedule::new(set.clone())
+            .with_threshold_keys(truncated.clone())
+            .err(),
+        Some(ThresholdKeysError::ShareCountMismatch {
+            shares: set.len() - 1,
+            validators: set.len()
+        })
+    );
+    let mut genesis_config = GenesisConfig {
+        chain_id: DOMAIN.chain_id,
+        validators: private_keys
+            .iter()
+            .map(Registration::new)
+            .collect(),
+        initial_state: vec![],
+        start_time: 0,
+        threshold_keys: Some(keys.clone()),
+    };
+    assert!(genesis_config.epochs().is_ok());
+    genesis_config.threshold_keys = Some(lowered);
+    assert_eq!(
+        genesis_config.epochs().err(),
+        Some(GenesisError::ThresholdKeys(lowered_error))
+    );
+
+    // The primary collects partials as votes arrive. A bad one does not
+    // spoil the QC, and too few make none.
+    let mut aggregator = PartialAggregator::new(keys.clone(), DOMAIN);
+    for partial in
+        [partials[0], shares[1].sign_vote(&other_vote, &DOMAIN)]
+    {
+        aggregator.insert(vote.clone(), partial);
+    }
+    aggregator.insert(vote.clone(), partials[2]);
+    assert!(aggregator
+        .certificate(&vote, producer)
+        .is_none());
+    assert!(aggregator
+        .certificate(&other_vote, producer)
+        .is_none());
+    aggregator.insert(vote.clone(), partials[3]);
+    assert_eq!(
+        aggregator.certificate(&vote, producer),
+        Some(Ok(certificate.clone()))
+    );
+
+    // New views may carry threshold QCs, and an AggQC forwards the
+    // highest of them
+    let view = 7;
+    let leader = key_of(set.leader(view));
+    let eta = NewView {
+        view,
+        certificate: QuorumCertificate::Threshold(certificate.clone()),
+    };
+    assert_eq!(eta.verify(&ctx), Ok(()));
+    assert_eq!(
+        eta.verify(&aggregate_ctx),
+        Err(CertificateError::NoGroupKey { view: 5 })
+    );
+    let stale = NewView {
+        view: 5,
+        ..eta.clone()
+    };
+    assert_eq!(
+        stale.verify(&ctx),
+        Err(CertificateError::StaleView {
+            expected: 4,
+            found: 5
+        })
+    );
+    let senders = &private_keys[..3];
+    let certificate = QuorumCertificate::from_newviews(
+        senders
+            .iter()
+            .map(|_| eta.clone())
+            .collect(),
+        senders
+            .iter()
+            .map(|key| {
+                SignedMessage::new_view(eta.clone(), &DOMAIN, key)
+                    .signature
+            })
+            .collect(),
+        senders
+            .iter()
+            .map(Signer::public_key)
+            .collect::<IndexSet<_>>(),
+        &set,
+        &DOMAIN,
+        leader,
+    )
+    .unwrap();
+    let QuorumCertificate::Sad(aggqc) = &certificate else {
+        unreachable!()
+    };
+    assert_eq!(aggqc.verify(view, &ctx), Ok(()));
+    assert_eq!(
+        aggqc.verify(view, &aggregate_ctx),
+        Err(CertificateError::NoGroupKey { view: 5 })
+    );
+    let QuorumCertificate::Threshold(threshold_qc) = &eta.certificate
+    else {
+        unreachable!()
+    };
+    assert_eq!(
+        aggqc.find_high_qc(),
+        Some(&HighQC::Threshold(threshold_qc.clone()))
+    );
+    assert_eq!(certificate.certified_vote(), Some(&vote));
+
+    // A seeded threshold cluster is dealt the same group key every time
+    let group_key = |seed| {
+        setup_seeded_threshold_cluster(4, seed)[0]
+            .epochs()
+            .threshold_keys(0)
+            .cloned()
+    };
+    assert!(group_key(7).is_some());
+    assert_eq!(group_key(7), group_key(7));
+    assert_ne!(group_key(7), group_key(8));
+
+    // A cluster dealt a group key at genesis certifies its blocks with
+    // threshold QCs, and stays live and in agreement
+    let endpoints = cluster::run(setup_threshold_cluster(4), VIEWS);
+    let longest = assert_agreement(&endpoints);
+    for endpoint in &endpoints {
+        assert!(endpoint.committed().len() + 2 >= longest.len());
+        assert!(matches!(
+            endpoint.high_qc(),
+            QuorumCertificate::Threshold(_)
+        ));
+    }
+    assert!(longest.len() > VIEWS as usize / 2);
+
+    println!("all threshold vectors passed");
+}
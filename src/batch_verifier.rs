@@ -0,0 +1,110 @@
+use crate::{
+    crypto::{Aggregator, Scheme, Verifier},
+    domain::SigningDomain,
+    message::SignedMessage,
+};
+
+/// Collects inbound messages and sigverifies them together.
+///
+/// The whole batch is first checked at once with a random linear
+/// combination of the signatures, which is much cheaper than one
+/// pairing check per message. If that fails, the batch is bisected
+/// until the invalid signatures are isolated, so a few bad messages
+/// only cost a logarithmic number of extra checks each.
+#[derive(Debug)]
+pub struct BatchVerifier {
+    /// Domain the messages must be signed in
+    domain: SigningDomain,
+
+    /// Pending messages with their signed payloads
+    pending: Vec<(SignedMessage, [u8; 32])>,
+}
+
+/// Outcome of verifying a batch. Both lists keep the order in which
+/// messages were pushed.
+#[derive(Debug, Default)]
+pub struct VerifiedBatch {
+    pub valid: Vec<SignedMessage>,
+    pub invalid: Vec<SignedMessage>,
+}
+
+impl BatchVerifier {
+    pub fn new(domain: SigningDomain) -> BatchVerifier {
+        BatchVerifier {
+            domain,
+            pending: vec![],
+        }
+    }
+
+    /// Queues a message for verification
+    pub fn push(&mut self, message: SignedMessage) {
+        let payload = message
+            .message_type
+            .signed_payload(&self.domain);
+        self.pending.push((message, payload));
+    }
+
+    /// Number of messages waiting for verification
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Verifies and drains every pending message
+    pub fn verify(&mut self) -> VerifiedBatch {
+        let pending = core::mem::take(&mut self.pending);
+        let mut valid = vec![false; pending.len()];
+        bisect(&pending, &mut valid);
+
+        let mut batch = VerifiedBatch::default();
+        for ((message, _), valid) in pending.into_iter().zip(valid) {
+            if valid {
+                batch.valid.push(message);
+            } else {
+                batch.invalid.push(message);
+            }
+        }
+        batch
+    }
+}
+
+/// Marks the messages of `pending` with valid signatures in `valid`,
+/// checking the whole slice at once and splitting it in halves when
+/// that fails
+fn bisect(pending: &[(SignedMessage, [u8; 32])], valid: &mut [bool]) {
+    match pending {
+        [] => {}
+
+        // A single check is cheaper without the linear combination
+        [(message, payload)] => {
+            valid[0] = message
+                .transmitter
+                .verify(payload, &message.signature);
+        }
+
+        _ => {
+            let batch: Vec<_> = pending
+                .iter()
+                .map(|(message, payload)| {
+                    (
+                        payload.as_slice(),
+                        &message.transmitter,
+                        &message.signature,
+                    )
+                })
+                .collect();
+            if Scheme::verify_batch(&batch) {
+                valid.fill(true);
+                return;
+            }
+
+            let mid = pending.len() / 2;
+            let (valid_left, valid_right) = valid.split_at_mut(mid);
+            bisect(&pending[..mid], valid_left);
+            bisect(&pending[mid..], valid_right);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Reference signer, holding a validator key outside of the validator
+//! process and refusing to double sign.
+//!
+//! ```text
+//! pfhs-signer --key <key file> --socket <path> [--state <path>]
+//!     [--chain-id <id>]
+//! ```
+//!
+//! The key file is generated if it does not exist yet. Set
+//! `PFHS_PASSPHRASE` if it is (to be) encrypted. Views signed so far are
+//! recorded in the state file, `<key file>.state` by default, so that a
+//! restarted signer does not sign anything conflicting.
+
+#[cfg(unix)]
+fn main() {
+    use std::{os::unix::fs::FileTypeExt, path::PathBuf, sync::Arc};
+
+    use pfhs::{
+        domain::SigningDomain,
+        keystore, remote_signer,
+        signer::{ConsensusSigner, GuardedSigner},
+    };
+
+    let mut key = None;
+    let mut socket = None;
+    let mut state = None;
+    let mut chain_id = 0;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().unwrap_or_else(|| {
+                usage(&format!("{arg} needs a value"))
+            })
+        };
+        match arg.as_str() {
+            "--key" => key = Some(PathBuf::from(value())),
+            "--socket" => socket = Some(PathBuf::from(value())),
+            "--state" => state = Some(PathBuf::from(value())),
+            "--chain-id" => {
+                chain_id = value()
+                    .parse()
+                    .unwrap_or_else(|_| usage("chain id is a u64"))
+            }
+            _ => usage(&format!("unknown argument {arg}")),
+        }
+    }
+    let key = key.unwrap_or_else(|| usage("--key is required"));
+    let socket =
+        socket.unwrap_or_else(|| usage("--socket is required"));
+    let state = state.unwrap_or_else(|| key.with_extension("state"));
+
+    let passphrase = std::env::var("PFHS_PASSPHRASE").ok();
+    let private_key = if key.exists() {
+        keystore::load(&key, passphrase.as_deref())
+    } else {
+        println!("generating key {}", key.display());
+        keystore::generate(&key, passphrase.as_deref())
+    }
+    .unwrap_or_else(|err| fail(&format!("{}: {err}", key.display())));
+    let signer = GuardedSigner::with_state_file(
+        private_key,
+        SigningDomain::new(chain_id),
+        &state,
+    )
+    .unwrap_or_else(|err| fail(&format!("{}: {err}", state.display())));
+
+    // Replace the socket of a previous run, but never anything else
+    if std::fs::symlink_metadata(&socket)
+        .is_ok_and(|metadata| metadata.file_type().is_socket())
+    {
+        std::fs::remove_file(&socket)
+            .unwrap_or_else(|err| fail(&err.to_string()));
+    }
+    let listener = std::os::unix::net::UnixListener::bind(&socket)
+        .unwrap_or_else(|err| {
+            fail(&format!("{}: {err}", socket.display()))
+        });
+
+    println!(
+        "signing for {} on {}",
+        signer.signer_public_key(),
+        socket.display()
+    );
+    if let Err(err) = remote_signer::serve(listener, Arc::new(signer)) {
+        fail(&err.to_string());
+    }
+}
+
+#[cfg(unix)]
+fn usage(reason: &str) -> ! {
+    fail(&format!(
+        "{reason}\nusage: pfhs-signer --key <key file> --socket <path> \
+         [--state <path>] [--chain-id <id>]"
+    ))
+}
+
+#[cfg(unix)]
+fn fail(reason: &str) -> ! {
+    eprintln!("pfhs-signer: {reason}");
+    std::process::exit(1)
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("pfhs-signer: only unix sockets are supported");
+    std::process::exit(1)
+}
@@ -1,7 +1,8 @@
-use borsh::BorshSerialize;
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    certificates::QuorumCertificate, crypto::Signature,
+    certificates::QuorumCertificate, domain::SigningDomain,
     transaction::Transaction,
 };
 
@@ -9,8 +10,96 @@ use crate::{
 pub struct Block {
     pub transactions: Vec<Transaction>,
     pub certificate: QuorumCertificate,
-    pub last_blockhash: Signature,
+    pub last_blockhash: BlockHash,
     pub view: u64,
 }
 
-// TODO: verify block has valid transactions
+impl Block {
+    /// Whether every transaction of the block verifies in `domain`
+    pub fn verify_transactions(&self, domain: &SigningDomain) -> bool {
+        self.transactions
+            .iter()
+            .all(|transaction| transaction.verify(domain))
+    }
+
+    /// Canonical header committing to the full contents of the block
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            view: self.view,
+            last_blockhash: self.last_blockhash,
+            certificate_digest: digest(&self.certificate),
+            transactions_digest: digest(&self.transactions),
+        }
+    }
+
+    /// Content address of the block, i.e. the digest of its header.
+    /// Anyone holding the block can recompute it.
+    pub fn hash(&self) -> BlockHash {
+        BlockHash(digest(&self.header()))
+    }
+}
+
+/// Fixed-size summary of a block that is hashed to obtain its
+/// `BlockHash`
+#[derive(
+    Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq, Eq,
+)]
+pub struct BlockHeader {
+    pub view: u64,
+    pub last_blockhash: BlockHash,
+    pub certificate_digest: [u8; 32],
+    pub transactions_digest: [u8; 32],
+}
+
+/// SHA-256 digest of a block header
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    BorshSerialize,
+    BorshDeserialize,
+    Hash,
+    PartialEq,
+    Eq,
+)]
+pub struct BlockHash(pub [u8; 32]);
+
+impl BlockHash {
+    /// All zero hash, e.g. a placeholder parent in tests. Genesis
+    /// blocks have the digest of their config as parent instead.
+    pub const ZERO: BlockHash = BlockHash([0; 32]);
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&bs58::encode(self.0).into_string())
+    }
+}
+
+/// SHA-256 of the borsh serialization of a value
+pub(crate) fn digest<T: BorshSerialize + ?Sized>(
+    value: &T,
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    borsh::to_writer(&mut HashWriter(&mut hasher), value)
+        .expect("writing to a hasher is infallible");
+    hasher.finalize().into()
+}
+
+/// Adapter to stream borsh serialization straight into a hasher
+struct HashWriter<'a>(&'a mut Sha256);
+
+impl std::io::Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    block::{Block, BlockHash},
+    crypto::PublicKey,
+};
+
+/// A block known to this node that has not yet been committed
+#[derive(Debug)]
+pub struct BlockNode {
+    pub block: Block,
+    pub blockhash: BlockHash,
+    pub leader: PublicKey,
+
+    /// Whether a certificate for this block has been seen, i.e. a
+    /// block carrying a certificate for this block was inserted
+    pub certified: bool,
+}
+
+impl BlockNode {
+    pub fn view(&self) -> u64 {
+        self.block.view
+    }
+
+    pub fn parent_hash(&self) -> BlockHash {
+        self.block.last_blockhash
+    }
+}
+
+/// Fork-aware store of the uncommitted blocks above the last committed
+/// block (the root). Blocks point to their parents by hash, so several
+/// branches can coexist until one of them commits.
+#[derive(Debug)]
+pub struct BlockTree {
+    /// Uncommitted blocks keyed by their hash
+    nodes: HashMap<BlockHash, BlockNode>,
+
+    /// Hashes of the blocks proposed in each view. A view holds more
+    /// than one block only if its leader equivocated.
+    views: BTreeMap<u64, Vec<BlockHash>>,
+
+    /// Hash and view of the last committed block
+    root: (BlockHash, u64),
+}
+
+impl BlockTree {
+    /// Starts a tree whose root is the (committed) genesis block
+    pub fn new(genesis: BlockHash) -> BlockTree {
+        BlockTree {
+            nodes: HashMap::new(),
+            views: BTreeMap::new(),
+            root: (genesis, 0),
+        }
+    }
+
+    /// Hash of the last committed block
+    pub fn root(&self) -> BlockHash {
+        self.root.0
+    }
+
+    /// View of the last committed block
+    pub fn root_view(&self) -> u64 {
+        self.root.1
+    }
+
+    pub fn get(&self, blockhash: &BlockHash) -> Option<&BlockNode> {
+        self.nodes.get(blockhash)
+    }
+
+    /// Blocks proposed in a view
+    pub fn at_view(
+        &self,
+        view: u64,
+    ) -> impl Iterator<Item = &BlockNode> + '_ {
+        self.views
+            .get(&view)
+            .into_iter()
+            .flatten()
+            .filter_map(|hash| self.nodes.get(hash))
+    }
+
+    /// Parent of a block, if it is known and uncommitted
+    pub fn parent(&self, node: &BlockNode) -> Option<&BlockNode> {
+        self.nodes.get(&node.parent_hash())
+    }
+
+    /// Uncommitted block with the highest view
+    pub fn highest(&self) -> Option<&BlockNode> {
+        self.views
+            .values()
+            .rev()
+            .flatten()
+            .find_map(|hash| self.nodes.get(hash))
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Inserts a block under its content hash. The block certified by
+    /// the certificate it carries, if known, is marked certified.
+    /// Returns false if the block is already known or does not extend
+    /// past the root.
+    pub fn insert(&mut self, block: Block, leader: PublicKey) -> bool {
+        let blockhash = block.hash();
+        if block.view <= self.root_view()
+            || self.nodes.contains_key(&blockhash)
+        {
+            return false;
+        }
+
+        if let Some(certified) = block
+            .certificate
+            .certified_blockhash()
+            .and_then(|hash| self.nodes.get_mut(&hash))
+        {
+            certified.certified = true;
+        }
+
+        self.views
+            .entry(block.view)
+            .or_default()
+            .push(blockhash);
+        self.nodes.insert(
+            blockhash,
+            BlockNode {
+                block,
+                blockhash,
+                leader,
+                certified: false,
+            },
+        );
+        true
+    }
+
+    /// Two-chain commit rule of Fast-HotStuff.
+    ///
+    /// Let `b*` be the block with hash `latest`, `b'` its parent and
+    /// `b` the parent of `b'`. Then `b` (and all of its ancestors)
+    /// commits iff `b'` is certified by the certificate in `b*`, `b` is
+    /// certified by the certificate in `b'`, and `b'` was proposed in
+    /// the view directly after `b`, i.e. `b` has a direct, certified
+    /// child. Returns the hash of the block to commit.
+    pub fn commit_candidate(
+        &self,
+        latest: &BlockHash,
+    ) -> Option<BlockHash> {
+        let latest = self.nodes.get(latest)?;
+        let child = self.parent(latest).filter(|child| {
+            latest
+                .block
+                .certificate
+                .certified_blockhash()
+                == Some(child.blockhash)
+        })?;
+        let committed = self
+            .parent(child)
+            .filter(|committed| committed.certified)?;
+
+        (child.certified && child.view() == committed.view() + 1)
+            .then_some(committed.blockhash)
+    }
+
+    /// Commits a block and its uncommitted ancestors, returning them in
+    /// commit order (oldest first). The committed block becomes the new
+    /// root, and every branch that does not descend from it is pruned.
+    pub fn commit(&mut self, blockhash: &BlockHash) -> Vec<BlockNode> {
+        // Gather the committed chain, newest first
+        let mut chain = vec![];
+        let mut cursor = *blockhash;
+        while let Some(node) = self.nodes.remove(&cursor) {
+            cursor = node.parent_hash();
+            chain.push(node);
+        }
+        let Some(new_root) = chain.first() else {
+            return chain;
+        };
+        self.root = (new_root.blockhash, new_root.view());
+
+        // Prune everything that does not extend the new root
+        let surviving: HashSet<BlockHash> = self
+            .nodes
+            .keys()
+            .copied()
+            .filter(|hash| self.extends_root(hash))
+            .collect();
+        self.nodes
+            .retain(|hash, _| surviving.contains(hash));
+        self.views = self
+            .views
+            .split_off(&(self.root_view() + 1));
+        for hashes in self.views.values_mut() {
+            hashes.retain(|hash| self.nodes.contains_key(hash));
+        }
+        self.views
+            .retain(|_, hashes| !hashes.is_empty());
+
+        chain.reverse();
+        chain
+    }
+
+    /// Whether a block descends from the root through known blocks
+    fn extends_root(&self, blockhash: &BlockHash) -> bool {
+        let mut cursor = blockhash;
+        while let Some(node) = self.nodes.get(cursor) {
+            if node.parent_hash() == self.root() {
+                return true;
+            }
+            cursor = &node.block.last_blockhash;
+        }
+        false
+    }
+}
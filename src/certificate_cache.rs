@@ -0,0 +1,74 @@
+use std::{
+    cell::RefCell,
+    collections::{HashSet, VecDeque},
+};
+
+/// Digests of certificates that passed verification, so that a QC
+/// showing up again (e.g. in a block, then in new views, then as the
+/// high QC of an AggQC) is only pairing-checked once.
+///
+/// Holds at most `capacity` digests and forgets the oldest first. An
+/// evicted certificate is simply verified again. Only successes are
+/// remembered, so an invalid certificate never poisons the cache.
+///
+/// Uses interior mutability so that it can be consulted through the
+/// shared `VerifyContext` while certificates are verified recursively.
+#[derive(Debug)]
+pub struct CertificateCache {
+    capacity: usize,
+    digests: RefCell<Digests>,
+}
+
+#[derive(Debug, Default)]
+struct Digests {
+    members: HashSet<[u8; 32]>,
+
+    /// Insertion order, oldest first
+    order: VecDeque<[u8; 32]>,
+}
+
+impl CertificateCache {
+    pub fn new(capacity: usize) -> CertificateCache {
+        CertificateCache {
+            capacity,
+            digests: RefCell::default(),
+        }
+    }
+
+    /// Whether a certificate with this digest was verified
+    pub fn contains(&self, digest: &[u8; 32]) -> bool {
+        self.digests
+            .borrow()
+            .members
+            .contains(digest)
+    }
+
+    /// Remembers a verified certificate, evicting the oldest one if
+    /// the cache is full
+    pub fn insert(&self, digest: [u8; 32]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut digests = self.digests.borrow_mut();
+        if !digests.members.insert(digest) {
+            return;
+        }
+        digests.order.push_back(digest);
+        if digests.order.len() > self.capacity {
+            let oldest = digests
+                .order
+                .pop_front()
+                .expect("cache is over capacity");
+            digests.members.remove(&oldest);
+        }
+    }
+
+    /// Number of remembered certificates
+    pub fn len(&self) -> usize {
+        self.digests.borrow().order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -1,12 +1,21 @@
-use bls_signatures::{verify_messages, PrivateKey, Serialize};
+use std::collections::BTreeMap;
+
 use borsh::BorshSerialize;
 use indexmap::IndexSet;
 
 use crate::{
-    crypto::{aggregate_signatures, PublicKey, Signature},
-    message::{MessageType, NewView, Vote},
+    block::{digest, BlockHash},
+    certificate_cache::CertificateCache,
+    crypto::{Aggregator, PublicKey, Scheme, Signature, Verifier},
+    domain::{SignatureKind, SigningDomain},
+    epoch::EpochSchedule,
+    message::{MessageType, NewView, NewViewSummary, Vote},
+    signer::{ConsensusSigner, SignError, SignRequest},
+    threshold::ThresholdQC,
+    validator_set::{SignerBitmap, ValidatorSet},
 };
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, BorshSerialize, Hash, PartialEq, Eq, Clone)]
 pub enum QuorumCertificate {
     /// Happy certificate is constructed if the primary receives
@@ -22,310 +31,748 @@ pub enum QuorumCertificate {
     /// In this case, the qc signatures are aggregated (AggQC).
     Sad(AggQC),
 
+    /// Threshold certificate is constructed instead of a happy one in
+    /// epochs with a group key, see `threshold`.
+    ///
+    /// In this case, the partial vote signatures are combined into one
+    /// signature by the group key.
+    Threshold(ThresholdQC),
+
     Genesis,
 }
 
 impl QuorumCertificate {
+    /// The vote this certificate certifies. An AggQC certifies the
+    /// block of its highest QC. Returns None if the certified block is
+    /// genesis.
+    pub fn certified_vote(&self) -> Option<&Vote> {
+        match self {
+            QuorumCertificate::Happy(qc) => Some(&qc.vote),
+            QuorumCertificate::Sad(aggqc) => {
+                aggqc.find_high_qc().map(HighQC::vote)
+            }
+            QuorumCertificate::Threshold(qc) => Some(&qc.vote),
+            QuorumCertificate::Genesis => None,
+        }
+    }
+
+    /// Hash of the block this certificate certifies, or None for
+    /// genesis
+    pub fn certified_blockhash(&self) -> Option<BlockHash> {
+        self.certified_vote()
+            .map(|vote| vote.blockhash)
+    }
+
     /// At this stage, it is assumed all vote signatures have been
     /// verified and that they are all for the same view (current_view -
     /// 1), and that the number corresponds to the supermajority (2f+1).
     pub fn from_votes(
         vote: Vote,
-        vote_signatures: &Vec<Signature>,
+        vote_signatures: &[Signature],
         signers: IndexSet<PublicKey>,
-        signer: &PrivateKey,
-    ) -> QuorumCertificate {
-        let aggregated_signature = aggregate_signatures(&vote_signatures).expect("all messages have been sigverified and are guaranteed to be unique due to pubkey prepend");
-        QuorumCertificate::Happy(QC {
+        validator_set: &ValidatorSet,
+        domain: &SigningDomain,
+        signer: &impl ConsensusSigner,
+    ) -> Result<QuorumCertificate, SignError> {
+        let aggregated_signature =
+            Scheme::aggregate_signatures(vote_signatures)
+                .expect("there is at least one signature to aggregate");
+        QuorumCertificate::from_aggregate(
+            vote,
+            aggregated_signature,
+            SignerBitmap::from_signers(validator_set, &signers)
+                .expect("all signers are in the validator set"),
+            domain,
+            signer,
+        )
+    }
+
+    /// Same as `from_votes`, for vote signatures that have already been
+    /// aggregated, e.g. by a `VoteAggregator`
+    pub fn from_aggregate(
+        vote: Vote,
+        aggregated_signature: Signature,
+        signers: SignerBitmap,
+        domain: &SigningDomain,
+        signer: &impl ConsensusSigner,
+    ) -> Result<QuorumCertificate, SignError> {
+        let producer = signer.signer_public_key();
+        let signature = signer.sign_request(
+            &SignRequest::Qc {
+                view: vote.view,
+                producer,
+                aggregated_signature,
+            },
+            domain,
+        )?;
+        Ok(QuorumCertificate::Happy(QC {
             vote,
             aggregated_signature,
             signers,
-            signature: Signature({
-                // TODO: this allocates which is sad
-                let mut message = signer.public_key().as_bytes();
-                borsh::to_writer(
-                    &mut message,
-                    &aggregated_signature.as_bytes(),
-                )
-                .unwrap();
-                signer.sign(message)
-            }),
-            producer: PublicKey(signer.public_key()),
-        })
+            signature,
+            producer,
+        }))
     }
 
     /// At this stage, it is assumed all new view signatures have been
-    /// verified and that they are all for the same view (current_view),
-    /// and that the number corresponds to the supermajority (2f+1).
+    /// verified, that all new views are valid and for the same view
+    /// (current_view), and that the number corresponds to the
+    /// supermajority (2f+1).
+    ///
+    /// `etas`, `eta_signatures` and `signers` must be aligned, i.e. the
+    /// i-th signer sent the i-th new view with the i-th signature.
     pub fn from_newviews(
         etas: Vec<NewView>,
         eta_signatures: Vec<Signature>,
         signers: IndexSet<PublicKey>,
-        signer: &PrivateKey,
-    ) -> QuorumCertificate {
-        // Gather all qcs
-        let qcs = etas
+        validator_set: &ValidatorSet,
+        domain: &SigningDomain,
+        signer: &impl ConsensusSigner,
+    ) -> Result<QuorumCertificate, SignError> {
+        assert_eq!(
+            etas.len(),
+            signers.len(),
+            "one new view per signer"
+        );
+        assert_eq!(
+            eta_signatures.len(),
+            signers.len(),
+            "one signature per signer"
+        );
+        let new_view_aggregated_signature =
+            Scheme::aggregate_signatures(&eta_signatures)
+                .expect("there is at least one signature to aggregate");
+        let view = etas[0].view;
+
+        // Claimed views are stored in canonical signer order so they
+        // line up with the bitmap
+        let mut indexed: Vec<(u64, u64)> = signers
+            .iter()
+            .map(|signer| {
+                validator_set
+                    .index_of(signer)
+                    .expect("all signers are in the validator set")
+            })
+            .zip(etas.iter().map(NewView::high_qc_view))
+            .collect();
+        indexed.sort_by_key(|(index, _)| *index);
+        let mut bitmap = SignerBitmap::new(validator_set.len());
+        let high_qc_views = indexed
             .into_iter()
-            .flat_map(|eta| match eta.certificate {
-                QuorumCertificate::Happy(qc) => Some(qc),
-                _ => None,
+            .map(|(index, high_qc_view)| {
+                bitmap.insert(index);
+                high_qc_view
             })
             .collect();
-        let new_view_aggregated_signature =
-            aggregate_signatures(&eta_signatures)
-            .expect("all messages have been sigverified and are guaranteed to be unique due to pubkey prepend");
 
-        QuorumCertificate::Sad(AggQC {
-            qcs,
+        // Only the highest QC is forwarded
+        let high_qc = etas
+            .into_iter()
+            .filter_map(|eta| match eta.certificate {
+                QuorumCertificate::Happy(qc) => Some(HighQC::from(qc)),
+                QuorumCertificate::Threshold(qc) => {
+                    Some(HighQC::from(qc))
+                }
+                _ => None,
+            })
+            .max_by_key(|high_qc| high_qc.vote().view);
+
+        let producer = signer.signer_public_key();
+        let signature = signer.sign_request(
+            &SignRequest::AggQc {
+                view,
+                producer,
+                aggregated_signature: new_view_aggregated_signature,
+            },
+            domain,
+        )?;
+
+        Ok(QuorumCertificate::Sad(AggQC {
+            high_qc,
+            high_qc_views,
             aggregated_signature: new_view_aggregated_signature,
-            signers,
-            signature: Signature({
-                // TODO: this allocates which is sad
-                let mut message = signer.public_key().as_bytes();
-                borsh::to_writer(
-                    &mut message,
-                    &new_view_aggregated_signature.as_bytes(),
+            signers: bitmap,
+            signature,
+            producer,
+        }))
+    }
+}
+
+/// Reason a certificate failed validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateError {
+    /// Fewer distinct signers than the quorum threshold of the epoch
+    InsufficientQuorum { signers: usize, threshold: usize },
+
+    /// The signer bitmap is not sized for the validator set of the
+    /// epoch
+    MalformedSignerBitmap { expected: usize, found: usize },
+
+    /// The signer bitmap has a bit set past the end of the validator
+    /// set of the epoch
+    UnknownSigner { index: u64 },
+
+    /// The aggregated signature does not verify for the signers and
+    /// the messages they are claimed to have signed
+    BadAggregateSignature,
+
+    /// The producer's signature over the aggregated signature does not
+    /// verify
+    BadProducerSignature,
+
+    /// The producer is not the leader that was scheduled to build the
+    /// certificate
+    UnexpectedProducer(Box<PublicKey>),
+
+    /// A view is not the one expected in its context. For a high QC,
+    /// `expected` is the highest acceptable view.
+    StaleView { expected: u64, found: u64 },
+
+    /// An AggQC does not carry the highest QC claimed by its signers,
+    /// or a new view carries an AggQC rather than a QC
+    MissingHighQc,
+
+    /// An AggQC does not carry exactly one claimed high QC view per
+    /// signer
+    SignerCountMismatch { signers: usize, claims: usize },
+
+    /// A threshold QC certifies a view whose epoch has no group key
+    NoGroupKey { view: u64 },
+}
+
+impl std::fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CertificateError::InsufficientQuorum {
+                signers,
+                threshold,
+            } => {
+                write!(f, "{signers} signers, quorum is {threshold}")
+            }
+            CertificateError::MalformedSignerBitmap {
+                expected,
+                found,
+            } => {
+                write!(f, "signer bitmap of {found} bytes, expected {expected}")
+            }
+            CertificateError::UnknownSigner { index } => {
+                write!(f, "unknown signer index {index}")
+            }
+            CertificateError::BadAggregateSignature => {
+                f.write_str("bad aggregate signature")
+            }
+            CertificateError::BadProducerSignature => {
+                f.write_str("bad producer signature")
+            }
+            CertificateError::UnexpectedProducer(producer) => {
+                write!(f, "unexpected producer {producer}")
+            }
+            CertificateError::StaleView { expected, found } => {
+                write!(f, "expected view {expected}, found {found}")
+            }
+            CertificateError::MissingHighQc => {
+                f.write_str("missing high qc")
+            }
+            CertificateError::SignerCountMismatch {
+                signers,
+                claims,
+            } => {
+                write!(
+                    f,
+                    "{claims} claimed views for {signers} signers"
                 )
-                .unwrap();
-                signer.sign(message)
-            }),
-            producer: PublicKey(signer.public_key()),
-        })
+            }
+            CertificateError::NoGroupKey { view } => {
+                write!(f, "no group key for view {view}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CertificateError {}
+
+/// What certificates are verified against
+#[derive(Clone, Copy, Debug)]
+pub struct VerifyContext<'a> {
+    /// Validator sets by epoch
+    pub epochs: &'a EpochSchedule,
+
+    /// Domain every signature in a certificate must be made in
+    pub domain: SigningDomain,
+
+    /// Certificates already known to be valid. A cached certificate is
+    /// accepted without checking it again, which relies on the
+    /// validator set of a view never changing once it is known.
+    pub cache: Option<&'a CertificateCache>,
+}
+
+impl<'a> VerifyContext<'a> {
+    /// Context verifying every certificate from scratch
+    pub fn new(
+        epochs: &'a EpochSchedule,
+        domain: SigningDomain,
+    ) -> VerifyContext<'a> {
+        VerifyContext {
+            epochs,
+            domain,
+            cache: None,
+        }
+    }
+
+    pub fn with_cache(
+        self,
+        cache: &'a CertificateCache,
+    ) -> VerifyContext<'a> {
+        VerifyContext {
+            cache: Some(cache),
+            ..self
+        }
+    }
+
+    /// Runs `verify` unless `certificate` is cached, and caches it if
+    /// it is valid
+    pub(crate) fn verify_once<T: BorshSerialize>(
+        &self,
+        certificate: &T,
+        verify: impl FnOnce() -> Result<(), CertificateError>,
+    ) -> Result<(), CertificateError> {
+        let Some(cache) = self.cache else {
+            return verify();
+        };
+        let digest = digest(certificate);
+        if cache.contains(&digest) {
+            return Ok(());
+        }
+        verify()?;
+        cache.insert(digest);
+        Ok(())
     }
 }
 
-#[derive(Debug, BorshSerialize, PartialEq, Eq, Clone)]
+#[derive(Debug, BorshSerialize, Hash, PartialEq, Eq, Clone)]
 pub struct QC {
     /// For a QC, quorum is signing for the same block (in prev view)
     pub vote: Vote,
     pub aggregated_signature: Signature,
-    #[borsh(serialize_with = "index_map_impl::serialize_index_set")]
-    pub signers: IndexSet<PublicKey>,
+    pub signers: SignerBitmap,
     pub signature: Signature,
     pub producer: PublicKey,
 }
 
 impl QC {
-    /// A QC is valid if
-    /// 1) number of signers is supermajority
-    /// 2) signers are in quorum
-    /// 3) aggregated signature is valid
-    pub fn valid(&self, quorum: &[PublicKey]) -> bool {
-        let is_supermajority = {
+    /// A QC is valid if, against the validator set of the epoch of the
+    /// certified view,
+    /// 1) producer is the leader of the view after the certified view,
+    ///    i.e. the primary the votes were sent to
+    /// 2) signer bitmap is well-formed for the validator set
+    /// 3) number of signers is supermajority
+    /// 4) producer signature is valid
+    /// 5) aggregated signature is valid for the aggregated key of the
+    ///    signers
+    pub fn verify(
+        &self,
+        ctx: &VerifyContext,
+    ) -> Result<(), CertificateError> {
+        ctx.verify_once(self, || self.verify_uncached(ctx))
+    }
+
+    fn verify_uncached(
+        &self,
+        ctx: &VerifyContext,
+    ) -> Result<(), CertificateError> {
+        let epochs = ctx.epochs;
+        let validator_set = epochs.validator_set(self.vote.view);
+
+        let expected_producer = {
             #[inline(always)]
-            || self.signers.len() > 2 * quorum.len() / 3
+            || verify_leader(&self.producer, self.vote.view + 1, epochs)
         };
 
-        let signers_in_quorum = {
+        let valid_producer_signature = {
             #[inline(always)]
             || {
-                for signer in &self.signers {
-                    if quorum
-                        .iter()
-                        .find(|peer| **peer == *signer)
-                        .is_none()
-                    {
-                        return false;
-                    }
-                }
-                true
+                verify_producer_signature(
+                    &ctx.domain,
+                    SignatureKind::QcProducer,
+                    self.vote.view,
+                    &self.producer,
+                    &self.signature,
+                    &self.aggregated_signature,
+                )
             }
         };
 
-        let valid_aggregated_signature = {
+        let signers_in_quorum = {
             #[inline(always)]
-            || {
-                // PERF TODO: this is super sad lol
-                let vote = MessageType::Vote(self.vote.clone());
-                let messages: Vec<Vec<u8>> = self
-                    .signers
-                    .iter()
-                    .map(|signer| {
-                        let mut message = signer.as_bytes();
-                        borsh::to_writer(&mut message, &vote).unwrap();
-                        message
-                    })
-                    .collect();
-                let vec_slice: Vec<&[u8]> = messages
-                    .iter()
-                    .map(|msg| msg.as_slice())
-                    .collect();
-                let slice_slice = vec_slice.as_slice();
-                let signers: Vec<bls_signatures::PublicKey> = self
-                    .signers
-                    .iter()
-                    .map(|pk| pk.0)
-                    .collect();
+            || decode_signers(&self.signers, validator_set)
+        };
 
-                verify_messages(
-                    &self.aggregated_signature,
-                    slice_slice,
-                    &signers,
-                )
+        let is_supermajority = {
+            #[inline(always)]
+            || verify_supermajority(self.signers.count(), validator_set)
+        };
+
+        let valid_aggregated_signature = {
+            #[inline(always)]
+            |signers: &[PublicKey]| {
+                // Every signer signed the same vote, so this is a single
+                // pairing check against the aggregated key
+                let message = MessageType::Vote(self.vote.clone())
+                    .signed_payload(&ctx.domain);
+                Scheme::aggregate_public_keys(signers)
+                    .verify(&message, &self.aggregated_signature)
+                    .then_some(())
+                    .ok_or(CertificateError::BadAggregateSignature)
             }
         };
 
-        // This is sorted by compute cost and will short circuit if one
-        // of them is false
-        is_supermajority()
-            && signers_in_quorum()
-            && valid_aggregated_signature()
+        // This is sorted by compute cost and will short circuit on the
+        // first error
+        expected_producer()?;
+        let signers = signers_in_quorum()?;
+        is_supermajority()?;
+        valid_producer_signature()?;
+        valid_aggregated_signature(&signers)
     }
 }
 
-#[derive(Debug, BorshSerialize, PartialEq, Eq, Clone)]
+/// Compact aggregate of the new views for a view: the highest QC among
+/// them, plus the view of every signer's high QC covered by a single
+/// aggregated signature. Its size grows linearly in the number of
+/// signers rather than quadratically.
+#[derive(Debug, BorshSerialize, Hash, PartialEq, Eq, Clone)]
 pub struct AggQC {
-    /// For an aggQC, quorum is signing for their own highQC
-    pub qcs: Vec<QC>,
+    /// Highest QC among the new views, None if every new view carried
+    /// the genesis certificate
+    pub high_qc: Option<HighQC>,
+
+    /// View of each signer's high QC (0 for genesis), in canonical
+    /// signer order
+    pub high_qc_views: Vec<u64>,
+
+    /// Aggregated signature over each signer's `NewViewSummary`
     pub aggregated_signature: Signature,
-    #[borsh(serialize_with = "index_map_impl::serialize_index_set")]
-    pub signers: IndexSet<PublicKey>,
+    pub signers: SignerBitmap,
 
     pub signature: Signature,
     pub producer: PublicKey,
 }
 
 impl AggQC {
-    /// An AggQC is valid if
-    /// 1) number of qcs is supermajority
-    /// 2) signers are in quorum
-    /// 3) high qc is valid
-    /// 4) aggregated signature is valid
-    pub fn valid(&self, quorum: &[PublicKey]) -> bool {
-        let is_supermajority = {
+    /// An AggQC is valid if, against the validator set of the epoch of
+    /// `view` (the view whose new views were aggregated),
+    /// 1) producer is the leader of `view`
+    /// 2) there is exactly one claimed view per signer
+    /// 3) signer bitmap is well-formed for the validator set
+    /// 4) number of signers is supermajority
+    /// 5) producer signature is valid
+    /// 6) the high QC is for the highest claimed view (and is absent if
+    ///    that is genesis), i.e. no signer claims a higher one
+    /// 7) high QC is below `view` and valid for its own epoch
+    /// 8) aggregated signature is valid for the signers' summaries
+    pub fn verify(
+        &self,
+        view: u64,
+        ctx: &VerifyContext,
+    ) -> Result<(), CertificateError> {
+        ctx.verify_once(&(view, self), || {
+            self.verify_uncached(view, ctx)
+        })
+    }
+
+    fn verify_uncached(
+        &self,
+        view: u64,
+        ctx: &VerifyContext,
+    ) -> Result<(), CertificateError> {
+        let epochs = ctx.epochs;
+        let validator_set = epochs.validator_set(view);
+
+        let expected_producer = {
+            #[inline(always)]
+            || verify_leader(&self.producer, view, epochs)
+        };
+
+        let valid_producer_signature = {
             #[inline(always)]
-            || self.signers.len() > 2 * quorum.len() / 3
+            || {
+                verify_producer_signature(
+                    &ctx.domain,
+                    SignatureKind::AggQcProducer,
+                    view,
+                    &self.producer,
+                    &self.signature,
+                    &self.aggregated_signature,
+                )
+            }
+        };
+
+        let one_view_per_signer = {
+            #[inline(always)]
+            || {
+                let signers = self.signers.count();
+                let claims = self.high_qc_views.len();
+                (claims == signers).then_some(()).ok_or(
+                    CertificateError::SignerCountMismatch {
+                        signers,
+                        claims,
+                    },
+                )
+            }
         };
 
         let signers_in_quorum = {
+            #[inline(always)]
+            || decode_signers(&self.signers, validator_set)
+        };
+
+        let is_supermajority = {
+            #[inline(always)]
+            || verify_supermajority(self.signers.count(), validator_set)
+        };
+
+        let highest_claim = {
             #[inline(always)]
             || {
-                for signer in &self.signers {
-                    if quorum
-                        .iter()
-                        .find(|peer| **peer == *signer)
-                        .is_none()
-                    {
-                        return false;
-                    }
+                let highest = self
+                    .high_qc_views
+                    .iter()
+                    .copied()
+                    .max()
+                    .unwrap_or(0);
+                let included = self
+                    .high_qc
+                    .as_ref()
+                    .map_or(0, |high_qc| high_qc.vote().view);
+                if highest > included {
+                    // Some signer has a higher QC than the one included
+                    return Err(CertificateError::MissingHighQc);
                 }
-                true
+                if highest < included {
+                    return Err(CertificateError::StaleView {
+                        expected: highest,
+                        found: included,
+                    });
+                }
+                Ok(())
             }
         };
 
         let valid_high_qc = {
             #[inline(always)]
             || {
-                let mut high_qc = None;
-                let mut high_qc_view = 0;
-                for qc in &self.qcs {
-                    if qc.vote.view > high_qc_view {
-                        high_qc_view = qc.vote.view;
-                        high_qc = Some(qc);
-                    }
+                // No high qc means every new view carried the genesis
+                // certificate
+                let Some(high_qc) = &self.high_qc else {
+                    return Ok(());
+                };
+                if high_qc.vote().view >= view {
+                    return Err(CertificateError::StaleView {
+                        expected: view.saturating_sub(1),
+                        found: high_qc.vote().view,
+                    });
                 }
-
-                high_qc.unwrap().valid(quorum)
+                high_qc.verify(ctx)
             }
         };
 
         let valid_aggregated_signature = {
             #[inline(always)]
-            || {
-                // PERF TODO: this is super sad lol
-                let messages: Vec<Vec<u8>> = self
-                    .signers
+            |signers: &[PublicKey]| {
+                // Signers claiming the same high qc view signed the same
+                // summary, so there is one pairing per distinct claim
+                // against the aggregated key of its signers
+                let mut claims =
+                    BTreeMap::<u64, Vec<&PublicKey>>::new();
+                for (signer, &high_qc_view) in signers
                     .iter()
-                    .zip(self.qcs.iter())
-                    .map(|(signer, qc)| {
-                        let mut message = signer.as_bytes();
-                        borsh::to_writer(
-                            &mut message,
-                            &qc.aggregated_signature.as_bytes(),
-                        )
-                        .unwrap();
-                        message
+                    .zip(self.high_qc_views.iter())
+                {
+                    claims
+                        .entry(high_qc_view)
+                        .or_default()
+                        .push(signer);
+                }
+                let messages: Vec<[u8; 32]> = claims
+                    .keys()
+                    .map(|&high_qc_view| {
+                        NewViewSummary { view, high_qc_view }
+                            .signed_payload(&ctx.domain)
                     })
                     .collect();
-                let vec_slice: Vec<&[u8]> = messages
+                let messages: Vec<&[u8]> = messages
                     .iter()
-                    .map(|msg| msg.as_slice())
+                    .map(|message| message.as_slice())
                     .collect();
-                let slice_slice = vec_slice.as_slice();
-                let signers: Vec<bls_signatures::PublicKey> = self
-                    .signers
-                    .iter()
-                    .map(|pk| pk.0)
+                let keys: Vec<PublicKey> = claims
+                    .into_values()
+                    .map(Scheme::aggregate_public_keys)
                     .collect();
 
-                verify_messages(
+                Scheme::verify_aggregate(
                     &self.aggregated_signature,
-                    slice_slice,
-                    &signers,
+                    &messages,
+                    &keys,
                 )
+                .then_some(())
+                .ok_or(CertificateError::BadAggregateSignature)
             }
         };
 
-        // This is sorted by compute cost and will short circuit if one
-        // of them is false
-        is_supermajority()
-            && signers_in_quorum()
-            && valid_high_qc()
-            && valid_aggregated_signature()
+        // This is sorted by compute cost and will short circuit on the
+        // first error
+        expected_producer()?;
+        one_view_per_signer()?;
+        let signers = signers_in_quorum()?;
+        is_supermajority()?;
+        highest_claim()?;
+        valid_producer_signature()?;
+        valid_high_qc()?;
+        valid_aggregated_signature(&signers)
     }
 
-    pub fn find_high_qc(&self) -> Option<&QC> {
-        let mut high_qc = None;
-        let mut high_qc_view = 0;
-        for qc in &self.qcs {
-            if qc.vote.view > high_qc_view {
-                high_qc_view = qc.vote.view;
-                high_qc = Some(qc);
-            }
+    /// The highest QC among the aggregated new views, or None if every
+    /// new view carried the genesis certificate
+    pub fn find_high_qc(&self) -> Option<&HighQC> {
+        self.high_qc.as_ref()
+    }
+}
+
+/// High QC forwarded by an AggQC, in either form a QC takes
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, BorshSerialize, Hash, PartialEq, Eq, Clone)]
+pub enum HighQC {
+    Aggregate(QC),
+    Threshold(ThresholdQC),
+}
+
+impl HighQC {
+    /// The vote the QC certifies
+    pub fn vote(&self) -> &Vote {
+        match self {
+            HighQC::Aggregate(qc) => &qc.vote,
+            HighQC::Threshold(qc) => &qc.vote,
         }
+    }
 
-        high_qc
+    pub fn verify(
+        &self,
+        ctx: &VerifyContext,
+    ) -> Result<(), CertificateError> {
+        match self {
+            HighQC::Aggregate(qc) => qc.verify(ctx),
+            HighQC::Threshold(qc) => qc.verify(ctx),
+        }
     }
 }
 
-mod index_map_impl {
-    use indexmap::IndexSet;
-
-    pub fn serialize_index_set<K, W>(
-        obj: &IndexSet<K>,
-        writer: &mut W,
-    ) -> ::core::result::Result<(), borsh::io::Error>
-    where
-        K: borsh::ser::BorshSerialize,
-        W: borsh::io::Write,
-    {
-        // TODO: this allocates which is sad
-        let values = obj.iter().collect::<Vec<_>>();
-        borsh::BorshSerialize::serialize(&values, writer)?;
-        Ok(())
+impl From<QC> for HighQC {
+    fn from(qc: QC) -> Self {
+        HighQC::Aggregate(qc)
     }
 }
 
-impl std::hash::Hash for QC {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.vote.hash(state);
-        self.aggregated_signature.hash(state);
-        for signer in &self.signers {
-            signer.hash(state);
-        }
-        self.signature.hash(state);
-        self.producer.hash(state);
+impl From<ThresholdQC> for HighQC {
+    fn from(qc: ThresholdQC) -> Self {
+        HighQC::Threshold(qc)
     }
 }
 
-impl std::hash::Hash for AggQC {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.qcs.hash(state);
-        self.aggregated_signature.hash(state);
-        for signer in &self.signers {
-            signer.hash(state);
+impl From<HighQC> for QuorumCertificate {
+    fn from(high_qc: HighQC) -> Self {
+        match high_qc {
+            HighQC::Aggregate(qc) => QuorumCertificate::Happy(qc),
+            HighQC::Threshold(qc) => QuorumCertificate::Threshold(qc),
         }
-        self.signature.hash(state);
-        self.producer.hash(state);
     }
 }
+
+/// Expands a signer bitmap into the keys of the validator set it refers
+/// to, in canonical order
+fn decode_signers(
+    signers: &SignerBitmap,
+    validator_set: &ValidatorSet,
+) -> Result<Vec<PublicKey>, CertificateError> {
+    let expected = validator_set.len().div_ceil(8);
+    if signers.as_bytes().len() != expected {
+        return Err(CertificateError::MalformedSignerBitmap {
+            expected,
+            found: signers.as_bytes().len(),
+        });
+    }
+
+    signers
+        .indices()
+        .map(|index| {
+            validator_set
+                .get(index)
+                .copied()
+                .ok_or(CertificateError::UnknownSigner { index })
+        })
+        .collect()
+}
+
+fn verify_supermajority(
+    signers: usize,
+    validator_set: &ValidatorSet,
+) -> Result<(), CertificateError> {
+    validator_set
+        .is_supermajority(signers)
+        .then_some(())
+        .ok_or(CertificateError::InsufficientQuorum {
+            signers,
+            threshold: validator_set.quorum_threshold(),
+        })
+}
+
+/// Checks the producer is the leader scheduled for `view`
+pub(crate) fn verify_leader(
+    producer: &PublicKey,
+    view: u64,
+    epochs: &EpochSchedule,
+) -> Result<(), CertificateError> {
+    (epochs.validator_set(view).leader(view) == producer)
+        .then_some(())
+        .ok_or_else(|| {
+            CertificateError::UnexpectedProducer(Box::new(*producer))
+        })
+}
+
+/// Checks the producer signed the aggregated signature (with the view
+/// and its publickey prepended)
+pub(crate) fn verify_producer_signature(
+    domain: &SigningDomain,
+    kind: SignatureKind,
+    view: u64,
+    producer: &PublicKey,
+    signature: &Signature,
+    aggregated_signature: &Signature,
+) -> Result<(), CertificateError> {
+    producer
+        .verify(
+            &producer_message(
+                domain,
+                kind,
+                view,
+                producer,
+                aggregated_signature,
+            ),
+            signature,
+        )
+        .then_some(())
+        .ok_or(CertificateError::BadProducerSignature)
+}
+
+/// Message signed by the producer of a certificate: the view it
+/// certifies and its publickey, followed by the aggregated signature,
+/// in the domain of the kind of certificate. Binding the view keeps the
+/// signature from being replayed under another one.
+pub(crate) fn producer_message(
+    domain: &SigningDomain,
+    kind: SignatureKind,
+    view: u64,
+    producer: &PublicKey,
+    aggregated_signature: &Signature,
+) -> [u8; 32] {
+    domain.signing_bytes(kind, &(view, producer, aggregated_signature))
+}
@@ -1,11 +1,28 @@
-use std::sync::mpsc::channel;
+use std::{
+    path::Path,
+    sync::mpsc::channel,
+    thread::JoinHandle,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use bls_signatures::PrivateKey;
-use rand::thread_rng;
+use rand::{rngs::StdRng, SeedableRng};
 
 use crate::{
-    crypto::PublicKey,
+    block::{digest, BlockHash},
+    crypto::{PrivateKey, Signer},
+    domain::{SigningDomain, PROTOCOL_VERSION},
     endpoint::{Endpoint, Identity, Peer},
+    genesis::GenesisConfig,
+    keystore::{self, KeystoreError},
+    signer::ConsensusSigner,
+    threshold,
+    validator_set::ValidatorSet,
+};
+
+/// Signing domain of every cluster set up here
+pub const DOMAIN: SigningDomain = SigningDomain {
+    chain_id: 0,
+    version: PROTOCOL_VERSION,
 };
 
 fn name_gen(i: u64) -> String {
@@ -21,27 +38,173 @@ fn name_gen(i: u64) -> String {
     }
 }
 
-pub fn setup_cluster(f: u64) -> Vec<Endpoint> {
-    let quorum_size = 3 * f + 1;
+/// Private key of the `index`-th node of the cluster set up from
+/// `seed`
+pub fn derive_key(seed: u64, index: u64) -> PrivateKey {
+    PrivateKey::from_seed(digest(&(b"pfhs cluster key", seed, index)))
+}
+
+/// Sets up a cluster of `validators` genesis validators. Any size is
+/// supported; fault tolerance and quorum thresholds are derived from it
+/// by the `ValidatorSet`.
+///
+/// Keys are derived from a random seed, which is printed so that the
+/// same cluster can be set up again with `setup_seeded_cluster`.
+pub fn setup_cluster(validators: u64) -> Vec<Endpoint> {
+    setup_cluster_with_standby(validators, 0)
+}
+
+/// Same as `setup_cluster`, with keys derived from `seed`
+pub fn setup_seeded_cluster(
+    validators: u64,
+    seed: u64,
+) -> Vec<Endpoint> {
+    setup_seeded_cluster_with_standby(validators, 0, seed)
+}
+
+/// Sets up a cluster of `validators` genesis validators plus `standby`
+/// nodes that are connected to everyone but only follow the chain until
+/// a reconfiguration adds them to the validator set. Standby nodes are
+/// the last `standby` endpoints returned.
+pub fn setup_cluster_with_standby(
+    validators: u64,
+    standby: u64,
+) -> Vec<Endpoint> {
+    let seed = rand::random();
+    println!("cluster seed is {seed}");
+    setup_seeded_cluster_with_standby(validators, standby, seed)
+}
 
+/// Same as `setup_cluster_with_standby`, with keys derived from `seed`
+pub fn setup_seeded_cluster_with_standby(
+    validators: u64,
+    standby: u64,
+    seed: u64,
+) -> Vec<Endpoint> {
     // Set up identities
     let mut identities = vec![];
-    for peer in 0..quorum_size {
+    for peer in 0..validators + standby {
         let name = name_gen(peer);
-        let private_key = PrivateKey::generate(&mut thread_rng());
-        identities.push(Identity {
-            name: name.leak(),
-            public_key: PublicKey(private_key.public_key()),
-            private_key: private_key,
-            index: peer as u64,
-        });
+        let private_key = derive_key(seed, peer);
+        identities.push(Identity::local(name.leak(), private_key));
+    }
+    setup_cluster_with_identities(identities, validators)
+}
+
+/// Same as `setup_cluster`, with the QCs of the genesis epoch threshold
+/// signed. The group key is dealt here and every validator is given its
+/// share, see `threshold`.
+pub fn setup_threshold_cluster(validators: u64) -> Vec<Endpoint> {
+    let seed = rand::random();
+    println!("cluster seed is {seed}");
+    setup_seeded_threshold_cluster(validators, seed)
+}
+
+/// Same as `setup_threshold_cluster`, with keys and the dealing of the
+/// group key derived from `seed`
+pub fn setup_seeded_threshold_cluster(
+    validators: u64,
+    seed: u64,
+) -> Vec<Endpoint> {
+    let identities = (0..validators)
+        .map(|peer| {
+            Identity::local(
+                name_gen(peer).leak(),
+                derive_key(seed, peer),
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut genesis_config = genesis_config(&identities, validators);
+    let validator_set = genesis_config
+        .validator_set()
+        .expect("genesis validators have valid proofs of possession");
+    let mut rng =
+        StdRng::from_seed(digest(&(b"pfhs threshold deal", seed)));
+    let (keys, shares) = threshold::deal(&validator_set, &mut rng);
+    genesis_config.threshold_keys = Some(keys);
+
+    let mut endpoints = connect(identities, genesis_config);
+    for endpoint in &mut endpoints {
+        let index = validator_set
+            .index_of(&endpoint.public_key())
+            .expect("every endpoint is a genesis validator");
+        endpoint.set_key_share(shares[index as usize].clone());
+    }
+    endpoints
+}
+
+/// Same as `setup_cluster`, with the key of every validator loaded from
+/// `<name>.key` in `directory`, or generated there on first use, so that
+/// validators keep their identities across runs
+pub fn setup_cluster_from_keystore(
+    directory: &Path,
+    validators: u64,
+) -> Result<Vec<Endpoint>, KeystoreError> {
+    let mut identities = vec![];
+    for peer in 0..validators {
+        let name: &'static str = name_gen(peer).leak();
+        let path = directory.join(format!("{name}.key"));
+        let identity = if path.exists() {
+            keystore::load_identity(&path, name, None)?
+        } else {
+            Identity::local(name, keystore::generate(&path, None)?)
+        };
+        identities.push(identity);
+    }
+    Ok(setup_cluster_with_identities(identities, validators))
+}
+
+/// Connects every identity to every other one. The first `validators`
+/// identities are the genesis validators. Identities may keep their
+/// keys in a remote signer, see `Identity::remote`.
+pub fn setup_cluster_with_identities(
+    identities: Vec<Identity>,
+    validators: u64,
+) -> Vec<Endpoint> {
+    let genesis_config = genesis_config(&identities, validators);
+    connect(identities, genesis_config)
+}
+
+/// Genesis shared by every endpoint, with the first `validators`
+/// identities as validators and aggregate QCs
+fn genesis_config(
+    identities: &[Identity],
+    validators: u64,
+) -> GenesisConfig {
+    GenesisConfig {
+        chain_id: DOMAIN.chain_id,
+        validators: identities
+            .iter()
+            .take(validators as usize)
+            .map(|identity| {
+                identity
+                    .signer
+                    .registration()
+                    .expect("genesis validators can register")
+            })
+            .collect(),
+        initial_state: vec![],
+        start_time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock is after the unix epoch")
+            .as_secs(),
+        threshold_keys: None,
     }
+}
+
+/// Connects every identity to every other one and starts them all from
+/// `genesis_config`
+fn connect(
+    identities: Vec<Identity>,
+    genesis_config: GenesisConfig,
+) -> Vec<Endpoint> {
+    let cluster_size = identities.len() as u64;
 
     // Set up peers
-    let mut peers: Vec<Vec<Peer>> = (0..quorum_size)
+    let mut peers: Vec<Vec<Peer>> = (0..cluster_size)
         .map(|_| vec![])
         .collect();
-    for one in 0..quorum_size {
+    for one in 0..cluster_size {
         for two in 0..one {
             let (sender_1, receiver_2) = channel();
             let (sender_2, receiver_1) = channel();
@@ -61,7 +224,62 @@ pub fn setup_cluster(f: u64) -> Vec<Endpoint> {
     // Set up endpoints
     let mut endpoints = vec![];
     for (identity, peers) in identities.into_iter().zip(peers) {
-        endpoints.push(Endpoint::new_genesis(identity, peers))
+        endpoints.push(Endpoint::new_genesis(
+            identity,
+            peers,
+            genesis_config.clone(),
+        ))
     }
     endpoints
 }
+
+/// Runs every endpoint for `views` views, each on its own thread, and
+/// returns them once they are all done
+pub fn run(endpoints: Vec<Endpoint>, views: u64) -> Vec<Endpoint> {
+    let handles: Vec<JoinHandle<Endpoint>> = endpoints
+        .into_iter()
+        .map(|mut endpoint| {
+            std::thread::spawn(move || {
+                endpoint.run(views);
+                endpoint
+            })
+        })
+        .collect();
+    handles
+        .into_iter()
+        .map(|handle| {
+            handle
+                .join()
+                .expect("endpoint panicked")
+        })
+        .collect()
+}
+
+/// Longest chain committed by any of `endpoints`. Panics unless every
+/// endpoint committed a prefix of it, i.e. unless they agree.
+pub fn assert_agreement(endpoints: &[Endpoint]) -> Vec<BlockHash> {
+    let longest = endpoints
+        .iter()
+        .map(Endpoint::committed)
+        .max_by_key(|committed| committed.len())
+        .expect("there is at least one endpoint");
+    for endpoint in endpoints {
+        let committed = endpoint.committed();
+        assert_eq!(
+            committed,
+            &longest[..committed.len()],
+            "{} committed a conflicting chain",
+            endpoint.public_key()
+        );
+    }
+    longest
+}
+
+/// Validator set of `validators` fresh random keys
+pub fn random_validator_set(validators: usize) -> ValidatorSet {
+    ValidatorSet::new(
+        (0..validators).map(|_| {
+            PrivateKey::from_seed(rand::random()).public_key()
+        }),
+    )
+}
@@ -1,70 +1,183 @@
-use bls_signatures::Serialize;
-use borsh::BorshSerialize;
-
-#[derive(Clone, Debug, PartialEq, Eq, Copy)]
-#[repr(transparent)]
-pub struct Signature(pub bls_signatures::Signature);
-
-pub fn aggregate_signatures(
-    sigs: &[Signature],
-) -> Result<Signature, bls_signatures::Error> {
-    bls_signatures::aggregate(
-        // SAFETY: transparent type
-        unsafe { core::mem::transmute(sigs) },
-    )
-    .map(Signature)
+//! Signature scheme used by consensus.
+//!
+//! Consensus code only uses the `PrivateKey`, `PublicKey` and
+//! `Signature` aliases below together with the `Signer`, `Verifier` and
+//! `Aggregator` traits, so the scheme can be swapped without touching
+//! it. BLS is the default; the `mock-crypto` feature switches to an
+//! insecure but fast scheme for simulations. Both also implement
+//! `Threshold`, used by `threshold` for constant-size certificates.
+
+use std::{fmt::Debug, fmt::Display, hash::Hash};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use rand::{CryptoRng, RngCore};
+
+use crate::block::digest;
+
+pub mod bls;
+pub mod mock;
+
+#[cfg(not(feature = "mock-crypto"))]
+pub type Scheme = bls::Bls;
+#[cfg(feature = "mock-crypto")]
+pub type Scheme = mock::Mock;
+
+pub type PrivateKey = <Scheme as SignatureScheme>::PrivateKey;
+pub type PublicKey = <Scheme as SignatureScheme>::PublicKey;
+pub type Signature = <Scheme as SignatureScheme>::Signature;
+pub type Accumulator = <Scheme as Aggregator>::Accumulator;
+
+/// Types making up a signature scheme
+pub trait SignatureScheme {
+    type PrivateKey: Signer<Self>;
+    type PublicKey: Verifier<Self>
+        + Copy
+        + Debug
+        + Display
+        + Eq
+        + Ord
+        + Hash
+        + BorshSerialize
+        + BorshDeserialize;
+    type Signature: Copy
+        + Debug
+        + Eq
+        + Hash
+        + BorshSerialize
+        + BorshDeserialize;
 }
 
-impl std::hash::Hash for Signature {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        // TODO: This allocates which is sad
-        self.0.as_bytes().hash(state);
-    }
+/// A secret key, producing signatures
+pub trait Signer<S: SignatureScheme + ?Sized>: Sized {
+    fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self;
+
+    /// Deterministically derives a key from a seed
+    fn from_seed(seed: [u8; 32]) -> Self;
+
+    fn public_key(&self) -> S::PublicKey;
+
+    fn sign(&self, message: &[u8]) -> S::Signature;
 }
 
-impl std::ops::Deref for Signature {
-    type Target = bls_signatures::Signature;
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
+/// A public key, checking signatures by its holder
+pub trait Verifier<S: SignatureScheme + ?Sized> {
+    fn verify(&self, message: &[u8], signature: &S::Signature) -> bool;
 }
 
-impl BorshSerialize for Signature {
-    fn serialize<W: std::io::prelude::Write>(
-        &self,
-        writer: &mut W,
-    ) -> std::io::Result<()> {
-        // TODO: This allocates which is sad
-        writer.write(&self.0.as_bytes())?;
-        Ok(())
-    }
+/// Combines signatures and keys, and verifies combined signatures
+pub trait Aggregator: SignatureScheme {
+    /// Running sum of signatures, which is cheaper to add to than to
+    /// aggregate into a `Signature` after every addition
+    type Accumulator: Clone + Debug;
+
+    /// Starts a running sum from a signature
+    fn accumulator(signature: &Self::Signature) -> Self::Accumulator;
+
+    /// Adds a signature to a running sum
+    fn add_assign(
+        accumulator: &mut Self::Accumulator,
+        signature: &Self::Signature,
+    );
+
+    /// Aggregate signature of the signatures added to a running sum
+    fn accumulated(accumulator: &Self::Accumulator) -> Self::Signature;
+
+    /// Combines signatures into one. Returns None if there are none.
+    fn aggregate_signatures<'a>(
+        signatures: impl IntoIterator<Item = &'a Self::Signature>,
+    ) -> Option<Self::Signature>
+    where
+        Self::Signature: 'a;
+
+    /// Combines public keys into one. With proofs of possession
+    /// registered for every key, an aggregate signature over a single
+    /// message verifies against the aggregated key.
+    fn aggregate_public_keys<'a>(
+        keys: impl IntoIterator<Item = &'a Self::PublicKey>,
+    ) -> Self::PublicKey
+    where
+        Self::PublicKey: 'a;
+
+    /// Verifies an aggregate signature where `keys[i]` signed
+    /// `messages[i]`. Messages must be distinct; signers of the same
+    /// message are expected to be combined into a single key.
+    fn verify_aggregate(
+        signature: &Self::Signature,
+        messages: &[&[u8]],
+        keys: &[Self::PublicKey],
+    ) -> bool;
+
+    /// Verifies independent signatures at once, the i-th by `key` over
+    /// `message` for the i-th `(message, key, signature)`. Returns true
+    /// if all of them are valid (up to a negligible probability of a
+    /// false positive). A false result does not tell which ones are
+    /// invalid.
+    fn verify_batch(
+        batch: &[(&[u8], &Self::PublicKey, &Self::Signature)],
+    ) -> bool;
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Copy)]
-#[repr(transparent)]
-pub struct PublicKey(pub bls_signatures::PublicKey);
+/// Threshold signatures: a key split into shares such that signatures
+/// over the same message by enough of the shares combine into a
+/// signature by the key itself
+pub trait Threshold: SignatureScheme {
+    /// Splits a fresh key into `shares` shares, any `threshold` of
+    /// which can sign for it. Returns the public key of the split key
+    /// and the shares, which sign and verify like any other key. Whoever
+    /// deals learns the key, so this is for tests and trusted setups
+    /// only.
+    fn deal<R: RngCore + CryptoRng>(
+        threshold: usize,
+        shares: usize,
+        rng: &mut R,
+    ) -> (Self::PublicKey, Vec<Self::PrivateKey>);
 
-impl std::hash::Hash for PublicKey {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        // TODO: This allocates which is sad
-        self.0.as_bytes().hash(state);
-    }
+    /// Combines signatures over the same message by distinct shares,
+    /// each given with the index of its share. The result is only valid
+    /// for at least as many shares as the threshold. Returns None if
+    /// there are none or an index repeats.
+    fn combine_shares(
+        partials: &[(usize, Self::Signature)],
+    ) -> Option<Self::Signature>;
 }
 
-impl std::ops::Deref for PublicKey {
-    type Target = bls_signatures::PublicKey;
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
+/// Hashed ahead of a public key to form the message signed as its proof
+/// of possession, so that it can never be mistaken for a consensus message
+const PROOF_OF_POSSESSION_PREFIX: &[u8] = b"pfhs proof of possession";
+
+/// A public key together with a proof of possession of its secret key,
+/// i.e. its holder's signature over the key itself.
+///
+/// Validators are only admitted with a valid proof. This rules out
+/// rogue-key attacks, where a key is crafted from other validators' keys
+/// so that an aggregate signature can be forged without their secrets.
+#[derive(Clone, Copy, Debug, BorshSerialize, Hash, PartialEq, Eq)]
+pub struct Registration {
+    pub public_key: PublicKey,
+    pub proof_of_possession: Signature,
 }
 
-impl BorshSerialize for PublicKey {
-    fn serialize<W: std::io::prelude::Write>(
-        &self,
-        writer: &mut W,
-    ) -> std::io::Result<()> {
-        // TODO: This allocates which is sad
-        writer.write(&self.0.as_bytes())?;
-        Ok(())
+impl Registration {
+    pub fn new(private_key: &PrivateKey) -> Registration {
+        let public_key = private_key.public_key();
+        Registration {
+            public_key,
+            proof_of_possession: private_key
+                .sign(&proof_of_possession_message(&public_key)),
+        }
+    }
+
+    /// Whether the proof of possession is valid for the key
+    pub fn verify(&self) -> bool {
+        self.public_key.verify(
+            &proof_of_possession_message(&self.public_key),
+            &self.proof_of_possession,
+        )
     }
 }
+
+pub(crate) fn proof_of_possession_message(
+    public_key: &PublicKey,
+) -> [u8; 32] {
+    digest(&(PROOF_OF_POSSESSION_PREFIX, public_key))
+}
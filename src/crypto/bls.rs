@@ -0,0 +1,408 @@
+//! BLS signatures over BLS12-381, the default scheme
+
+use std::collections::{HashMap, HashSet};
+
+use bls12_381::{G1Projective, G2Projective, Scalar};
+use bls_signatures::{verify_messages, Serialize};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rand::{thread_rng, CryptoRng, RngCore};
+
+use super::{Aggregator, SignatureScheme, Signer, Threshold, Verifier};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bls;
+
+impl SignatureScheme for Bls {
+    type PrivateKey = PrivateKey;
+    type PublicKey = PublicKey;
+    type Signature = Signature;
+}
+
+/// Length of a compressed public key
+pub const PUBLIC_KEY_BYTES: usize = 48;
+
+/// Length of a compressed signature
+pub const SIGNATURE_BYTES: usize = 96;
+
+/// Deliberately neither `Copy` nor printable, so that the secret is
+/// never duplicated or logged by accident
+#[derive(Clone, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct PrivateKey(pub bls_signatures::PrivateKey);
+
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PrivateKey(<redacted>)")
+    }
+}
+
+impl Signer<Bls> for PrivateKey {
+    fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        PrivateKey(bls_signatures::PrivateKey::generate(rng))
+    }
+
+    fn from_seed(seed: [u8; 32]) -> Self {
+        PrivateKey(bls_signatures::PrivateKey::new(seed))
+    }
+
+    fn public_key(&self) -> PublicKey {
+        self.0.public_key().into()
+    }
+
+    fn sign(&self, message: &[u8]) -> Signature {
+        self.0.sign(message).into()
+    }
+}
+
+/// A signature together with its compressed encoding, computed once so
+/// that comparing, hashing and serializing never allocate
+#[derive(Clone, Copy)]
+pub struct Signature {
+    signature: bls_signatures::Signature,
+    bytes: [u8; SIGNATURE_BYTES],
+}
+
+impl Signature {
+    /// Compressed encoding
+    pub fn as_bytes(&self) -> &[u8; SIGNATURE_BYTES] {
+        &self.bytes
+    }
+}
+
+impl From<bls_signatures::Signature> for Signature {
+    fn from(signature: bls_signatures::Signature) -> Self {
+        let mut bytes = [0; SIGNATURE_BYTES];
+        signature
+            .write_bytes(&mut bytes.as_mut_slice())
+            .expect("compressed signatures fit the buffer");
+        Signature { signature, bytes }
+    }
+}
+
+impl std::fmt::Debug for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Signature")
+            .field(&self.signature)
+            .finish()
+    }
+}
+
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for Signature {}
+
+impl std::hash::Hash for Signature {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state);
+    }
+}
+
+impl std::ops::Deref for Signature {
+    type Target = bls_signatures::Signature;
+    fn deref(&self) -> &Self::Target {
+        &self.signature
+    }
+}
+
+impl BorshSerialize for Signature {
+    fn serialize<W: std::io::prelude::Write>(
+        &self,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        writer.write_all(&self.bytes)
+    }
+}
+
+impl BorshDeserialize for Signature {
+    fn deserialize_reader<R: std::io::prelude::Read>(
+        reader: &mut R,
+    ) -> std::io::Result<Self> {
+        let mut bytes = [0; SIGNATURE_BYTES];
+        reader.read_exact(&mut bytes)?;
+        bls_signatures::Signature::from_bytes(&bytes)
+            .map(Signature::from)
+            .map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    err,
+                )
+            })
+    }
+}
+
+/// A public key together with its compressed encoding, computed once
+/// so that comparing, hashing and serializing never allocate
+#[derive(Clone, Copy)]
+pub struct PublicKey {
+    key: bls_signatures::PublicKey,
+    bytes: [u8; PUBLIC_KEY_BYTES],
+}
+
+impl PublicKey {
+    /// Compressed encoding
+    pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_BYTES] {
+        &self.bytes
+    }
+}
+
+impl From<bls_signatures::PublicKey> for PublicKey {
+    fn from(key: bls_signatures::PublicKey) -> Self {
+        let mut bytes = [0; PUBLIC_KEY_BYTES];
+        key.write_bytes(&mut bytes.as_mut_slice())
+            .expect("compressed keys fit the buffer");
+        PublicKey { key, bytes }
+    }
+}
+
+impl Verifier<Bls> for PublicKey {
+    fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+        verify_messages(&signature.signature, &[message], &[self.key])
+    }
+}
+
+impl std::fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PublicKey")
+            .field(&self.key)
+            .finish()
+    }
+}
+
+impl PartialEq for PublicKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for PublicKey {}
+
+/// Bytewise order of the compressed encodings
+impl Ord for PublicKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.bytes.cmp(&other.bytes)
+    }
+}
+
+impl PartialOrd for PublicKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::hash::Hash for PublicKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state);
+    }
+}
+
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Base58 takes at most 138 digits per 100 bytes
+        let mut encoded = [0; PUBLIC_KEY_BYTES * 138 / 100 + 1];
+        let len = bs58::encode(self.bytes)
+            .onto(&mut encoded[..])
+            .expect("buffer fits any encoded key");
+        f.write_str(
+            std::str::from_utf8(&encoded[..len])
+                .expect("base58 is ascii"),
+        )
+    }
+}
+
+impl std::ops::Deref for PublicKey {
+    type Target = bls_signatures::PublicKey;
+    fn deref(&self) -> &Self::Target {
+        &self.key
+    }
+}
+
+impl BorshSerialize for PublicKey {
+    fn serialize<W: std::io::prelude::Write>(
+        &self,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        writer.write_all(&self.bytes)
+    }
+}
+
+impl BorshDeserialize for PublicKey {
+    fn deserialize_reader<R: std::io::prelude::Read>(
+        reader: &mut R,
+    ) -> std::io::Result<Self> {
+        let mut bytes = [0; PUBLIC_KEY_BYTES];
+        reader.read_exact(&mut bytes)?;
+        bls_signatures::PublicKey::from_bytes(&bytes)
+            .map(PublicKey::from)
+            .map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    err,
+                )
+            })
+    }
+}
+
+impl Aggregator for Bls {
+    type Accumulator = G2Projective;
+
+    fn accumulator(signature: &Signature) -> G2Projective {
+        G2Projective::from(signature.signature)
+    }
+
+    fn add_assign(
+        accumulator: &mut G2Projective,
+        signature: &Signature,
+    ) {
+        *accumulator += G2Projective::from(signature.signature);
+    }
+
+    fn accumulated(accumulator: &G2Projective) -> Signature {
+        bls_signatures::Signature::from(*accumulator).into()
+    }
+
+    fn aggregate_signatures<'a>(
+        signatures: impl IntoIterator<Item = &'a Signature>,
+    ) -> Option<Signature> {
+        signatures
+            .into_iter()
+            .map(|signature| G2Projective::from(signature.signature))
+            .reduce(|sum, signature| sum + signature)
+            .map(|sum| bls_signatures::Signature::from(sum).into())
+    }
+
+    fn aggregate_public_keys<'a>(
+        keys: impl IntoIterator<Item = &'a PublicKey>,
+    ) -> PublicKey {
+        bls_signatures::PublicKey::from(
+            keys.into_iter()
+                .map(|pk| G1Projective::from(pk.key))
+                .fold(G1Projective::identity(), |sum, pk| sum + pk),
+        )
+        .into()
+    }
+
+    fn verify_aggregate(
+        signature: &Signature,
+        messages: &[&[u8]],
+        keys: &[PublicKey],
+    ) -> bool {
+        // One key per distinct message, negligible next to the pairings
+        let keys: Vec<bls_signatures::PublicKey> =
+            keys.iter().map(|pk| pk.key).collect();
+        verify_messages(&signature.signature, messages, &keys)
+    }
+
+    fn verify_batch(batch: &[(&[u8], &PublicKey, &Signature)]) -> bool {
+        // Every signature is weighted by a random 64 bit scalar so that
+        // invalid signatures cannot cancel each other out, except with
+        // probability 2^-64. The weighted
+        // keys of signers of the same message are summed, so that e.g.
+        // all votes for a block cost a single hash and miller loop.
+        let mut rng = thread_rng();
+        let mut combined_signature = G2Projective::identity();
+        let mut combined_keys: HashMap<&[u8], G1Projective> =
+            HashMap::new();
+        for (message, key, signature) in batch {
+            let key = G1Projective::from(key.key);
+            if bool::from(key.is_identity()) {
+                return false;
+            }
+            let weight = Scalar::from(rng.next_u64());
+            combined_signature +=
+                G2Projective::from(signature.signature) * weight;
+            *combined_keys
+                .entry(message)
+                .or_insert_with(G1Projective::identity) += key * weight;
+        }
+        if combined_keys.is_empty() {
+            return true;
+        }
+
+        let (hashes, keys): (Vec<_>, Vec<_>) = combined_keys
+            .into_iter()
+            .map(|(message, key)| {
+                (
+                    bls_signatures::hash(message),
+                    bls_signatures::PublicKey::from(key),
+                )
+            })
+            .unzip();
+        bls_signatures::verify(
+            &combined_signature.into(),
+            &hashes,
+            &keys,
+        )
+    }
+}
+
+impl Threshold for Bls {
+    fn deal<R: RngCore + CryptoRng>(
+        threshold: usize,
+        shares: usize,
+        rng: &mut R,
+    ) -> (PublicKey, Vec<PrivateKey>) {
+        assert!(
+            (1..=shares).contains(&threshold),
+            "threshold is between 1 and the number of shares"
+        );
+        // Shares are points of a random polynomial of degree threshold
+        // - 1, and the key is its value at 0
+        let coefficients: Vec<Scalar> = (0..threshold)
+            .map(|_| bls_signatures::PrivateKey::generate(rng).into())
+            .collect();
+        let shares = (1..=shares as u64)
+            .map(|x| {
+                let x = Scalar::from(x);
+                let share = coefficients
+                    .iter()
+                    .rev()
+                    .fold(Scalar::zero(), |sum, c| sum * x + c);
+                PrivateKey(share.into())
+            })
+            .collect();
+        let key = PrivateKey(coefficients[0].into());
+        (key.public_key(), shares)
+    }
+
+    fn combine_shares(
+        partials: &[(usize, Signature)],
+    ) -> Option<Signature> {
+        let indices: HashSet<usize> = partials
+            .iter()
+            .map(|(index, _)| *index)
+            .collect();
+        if partials.is_empty() || indices.len() != partials.len() {
+            return None;
+        }
+        // Share i is the polynomial at i + 1
+        let xs: Vec<Scalar> = partials
+            .iter()
+            .map(|(index, _)| Scalar::from(*index as u64 + 1))
+            .collect();
+
+        // Lagrange interpolation at 0, in the exponent
+        let combined = partials.iter().enumerate().fold(
+            G2Projective::identity(),
+            |sum, (i, (_, signature))| {
+                let coefficient = xs
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .fold(Scalar::one(), |product, (_, x)| {
+                        let denominator = Option::<Scalar>::from(
+                            (x - xs[i]).invert(),
+                        )
+                        .expect("share points are distinct");
+                        product * x * denominator
+                    });
+                sum + G2Projective::from(signature.signature)
+                    * coefficient
+            },
+        );
+        Some(bls_signatures::Signature::from(combined).into())
+    }
+}
@@ -0,0 +1,269 @@
+//! Insecure signature scheme for fast simulations.
+//!
+//! Keys and signatures are integers modulo a prime and a signature is
+//! the private key multiplied by a hash of the message, so signing and
+//! verifying cost one hash and aggregation is a sum. The public key
+//! equals the private key: anyone can forge signatures, so this must
+//! never be used outside of simulations and tests. It does behave like
+//! BLS otherwise, i.e. tampered messages, wrong signers and incomplete
+//! aggregates are all rejected, and keys can be split into threshold
+//! shares.
+
+use std::collections::HashSet;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use super::{Aggregator, SignatureScheme, Signer, Threshold, Verifier};
+
+/// Modulus of all arithmetic, the Mersenne prime 2^61 - 1
+const P: u64 = (1 << 61) - 1;
+
+fn add(a: u64, b: u64) -> u64 {
+    (a + b) % P
+}
+
+fn mul(a: u64, b: u64) -> u64 {
+    (a as u128 * b as u128 % P as u128) as u64
+}
+
+fn inverse(a: u64) -> u64 {
+    // Fermat: a^(p-2) = a^-1
+    let mut result = 1;
+    let (mut base, mut exponent) = (a, P - 2);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Nonzero, so signing is a bijection on message hashes
+fn nonzero(value: u64) -> u64 {
+    value % (P - 1) + 1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mock;
+
+impl SignatureScheme for Mock {
+    type PrivateKey = PrivateKey;
+    type PublicKey = PublicKey;
+    type Signature = Signature;
+}
+
+/// Neither `Copy` nor printable, like the BLS key, so that code
+/// copying or logging keys does not build with either scheme
+#[derive(Clone, PartialEq, Eq)]
+pub struct PrivateKey(pub u64);
+
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PrivateKey(<redacted>)")
+    }
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    BorshSerialize,
+    BorshDeserialize,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+pub struct PublicKey(pub u64);
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    BorshSerialize,
+    BorshDeserialize,
+    Hash,
+    PartialEq,
+    Eq,
+)]
+pub struct Signature(pub u64);
+
+/// Message hash as an integer
+fn hash(message: &[u8]) -> u64 {
+    let digest = Sha256::digest(message);
+    u64::from_le_bytes(digest[..8].try_into().unwrap()) % P
+}
+
+impl Signer<Mock> for PrivateKey {
+    fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        PrivateKey(nonzero(rng.next_u64()))
+    }
+
+    fn from_seed(seed: [u8; 32]) -> Self {
+        PrivateKey(nonzero(u64::from_le_bytes(
+            seed[..8].try_into().unwrap(),
+        )))
+    }
+
+    fn public_key(&self) -> PublicKey {
+        PublicKey(self.0)
+    }
+
+    fn sign(&self, message: &[u8]) -> Signature {
+        Signature(mul(self.0, hash(message)))
+    }
+}
+
+impl Verifier<Mock> for PublicKey {
+    fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+        mul(self.0, hash(message)) == signature.0
+    }
+}
+
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&bs58::encode(self.0.to_le_bytes()).into_string())
+    }
+}
+
+impl Aggregator for Mock {
+    type Accumulator = u64;
+
+    fn accumulator(signature: &Signature) -> u64 {
+        signature.0
+    }
+
+    fn add_assign(accumulator: &mut u64, signature: &Signature) {
+        *accumulator = add(*accumulator, signature.0);
+    }
+
+    fn accumulated(accumulator: &u64) -> Signature {
+        Signature(*accumulator)
+    }
+
+    fn aggregate_signatures<'a>(
+        signatures: impl IntoIterator<Item = &'a Signature>,
+    ) -> Option<Signature> {
+        signatures
+            .into_iter()
+            .map(|signature| signature.0)
+            .reduce(add)
+            .map(Signature)
+    }
+
+    fn aggregate_public_keys<'a>(
+        keys: impl IntoIterator<Item = &'a PublicKey>,
+    ) -> PublicKey {
+        PublicKey(
+            keys.into_iter()
+                .fold(0, |sum, key| add(sum, key.0)),
+        )
+    }
+
+    fn verify_aggregate(
+        signature: &Signature,
+        messages: &[&[u8]],
+        keys: &[PublicKey],
+    ) -> bool {
+        if messages.is_empty() || messages.len() != keys.len() {
+            return false;
+        }
+        let distinct: HashSet<&[u8]> =
+            messages.iter().copied().collect();
+        if distinct.len() != messages.len() {
+            return false;
+        }
+        let expected = messages
+            .iter()
+            .zip(keys)
+            .fold(0u64, |sum, (message, key)| {
+                add(sum, mul(key.0, hash(message)))
+            });
+        expected == signature.0
+    }
+
+    fn verify_batch(batch: &[(&[u8], &PublicKey, &Signature)]) -> bool {
+        // Verifying one by one is already cheaper than combining
+        batch
+            .iter()
+            .all(|(message, key, signature)| {
+                key.verify(message, signature)
+            })
+    }
+}
+
+impl Threshold for Mock {
+    fn deal<R: RngCore + CryptoRng>(
+        threshold: usize,
+        shares: usize,
+        rng: &mut R,
+    ) -> (PublicKey, Vec<PrivateKey>) {
+        assert!(
+            (1..=shares).contains(&threshold),
+            "threshold is between 1 and the number of shares"
+        );
+        // Shares are points of a random polynomial of degree threshold
+        // - 1, and the key is its value at 0
+        let coefficients: Vec<u64> = (0..threshold)
+            .map(|_| nonzero(rng.next_u64()))
+            .collect();
+        let shares = (1..=shares as u64)
+            .map(|x| {
+                PrivateKey(
+                    coefficients
+                        .iter()
+                        .rev()
+                        .fold(0, |sum, c| add(mul(sum, x), *c)),
+                )
+            })
+            .collect();
+        (PublicKey(coefficients[0]), shares)
+    }
+
+    fn combine_shares(
+        partials: &[(usize, Signature)],
+    ) -> Option<Signature> {
+        let xs = share_points(partials)?;
+        // Lagrange interpolation at 0
+        let combined = partials.iter().enumerate().fold(
+            0,
+            |sum, (i, (_, signature))| {
+                let coefficient = xs
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .fold(1, |product, (_, x)| {
+                        mul(
+                            product,
+                            mul(*x, inverse(add(*x, P - xs[i]))),
+                        )
+                    });
+                add(sum, mul(coefficient, signature.0))
+            },
+        );
+        Some(Signature(combined))
+    }
+}
+
+/// Point at which each share evaluates the polynomial, i.e. its index
+/// plus one. None if there are no shares or an index repeats.
+fn share_points(partials: &[(usize, Signature)]) -> Option<Vec<u64>> {
+    let indices: HashSet<usize> = partials
+        .iter()
+        .map(|(index, _)| *index)
+        .collect();
+    if partials.is_empty() || indices.len() != partials.len() {
+        return None;
+    }
+    Some(
+        partials
+            .iter()
+            .map(|(index, _)| *index as u64 + 1)
+            .collect(),
+    )
+}
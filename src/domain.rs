@@ -0,0 +1,84 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::block::digest;
+
+/// Version of the protocol, bound into every signature so that
+/// signatures never carry over between incompatible versions
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Hashed ahead of every signed payload. It differs from the proof of
+/// possession prefix, so neither can be mistaken for the other.
+const DOMAIN_TAG: &[u8; 19] = b"pfhs signing domain";
+
+/// What a signature is for
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    BorshSerialize,
+    BorshDeserialize,
+    Hash,
+    PartialEq,
+    Eq,
+)]
+pub enum SignatureKind {
+    Block,
+    Vote,
+    NewView,
+    Handshake,
+
+    /// Producer signature of a QC over its view and aggregated
+    /// signature
+    QcProducer,
+
+    /// Producer signature of an AggQC over its view and aggregated
+    /// signature
+    AggQcProducer,
+
+    Transaction,
+}
+
+/// Chain and protocol version a node signs for. Every signature is
+/// over `signing_bytes`, which binds the payload to this domain and to
+/// the kind of message, so that a signature produced in one context
+/// never verifies in another.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    BorshSerialize,
+    BorshDeserialize,
+    Hash,
+    PartialEq,
+    Eq,
+)]
+pub struct SigningDomain {
+    pub chain_id: u64,
+    pub version: u16,
+}
+
+impl SigningDomain {
+    /// Domain of the current protocol version on `chain_id`
+    pub fn new(chain_id: u64) -> SigningDomain {
+        SigningDomain {
+            chain_id,
+            version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Bytes signed for a payload of the given kind, the digest of the
+    /// payload behind the domain, so that nothing is buffered
+    pub fn signing_bytes<T: BorshSerialize + ?Sized>(
+        &self,
+        kind: SignatureKind,
+        payload: &T,
+    ) -> [u8; 32] {
+        digest(&(
+            DOMAIN_TAG,
+            kind,
+            self.chain_id,
+            self.version,
+            payload,
+        ))
+    }
+}
@@ -1,68 +1,180 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::VecDeque,
     sync::mpsc::{Receiver, Sender},
-    time::Instant,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use bls_signatures::{PrivateKey, Serialize};
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 
 use crate::{
-    block::Block,
-    certificates::{AggQC, QuorumCertificate, QC},
-    crypto::{PublicKey, Signature},
+    batch_verifier::{BatchVerifier, VerifiedBatch},
+    block::{Block, BlockHash},
+    block_tree::{BlockNode, BlockTree},
+    certificate_cache::CertificateCache,
+    certificates::{AggQC, QuorumCertificate, VerifyContext},
+    crypto::{PrivateKey, PublicKey, Registration, Signature, Signer},
+    domain::SigningDomain,
+    epoch::EpochSchedule,
+    genesis::GenesisConfig,
     message::{MessageType, NewView, SignedMessage, Vote},
+    signer::{ConsensusSigner, SignError, SignRequest},
+    threshold::{KeyShare, PartialAggregator, PartialSignature},
+    transaction::Transaction,
+    validator_set::ValidatorSet,
+    vote_aggregator::VoteAggregator,
 };
 
+#[cfg(unix)]
+use crate::remote_signer::RemoteSigner;
+
 const TIMEOUT_MILLIS: u128 = 4_000;
 
+/// Number of verified certificates remembered, comfortably more than
+/// are in flight at once
+const CERTIFICATE_CACHE_CAPACITY: usize = 1024;
+
 pub struct Endpoint {
     /// Identity of the peer
     identity: Identity,
-    peers: Vec<Peer>,
-    quorum: Vec<PublicKey>,
+    peers: IndexMap<PublicKey, Peer>,
+
+    /// Peers whose genesis differs from ours. They are kept only so
+    /// their channels stay open; we never read from or send to them.
+    refused_peers: Vec<Peer>,
+
+    /// Validator sets by epoch, starting from the genesis set
+    epochs: EpochSchedule,
+
+    /// Certificates we already verified
+    certificate_cache: CertificateCache,
+
+    /// Transactions to include in our next proposal
+    pending_transactions: Vec<Transaction>,
 
     // Instead of sending to ourselves via channel, we keep a self_vote
     self_vote: Option<SignedMessage>,
 
+    // Same for our new view when we are the next primary
+    self_new_view: Option<SignedMessage>,
+
+    /// Highest QC we know of, sent in our new views when a view fails
+    high_qc: QuorumCertificate,
+
+    /// Our share of the group key, if we were dealt one, see
+    /// `threshold`
+    key_share: Option<KeyShare>,
+
+    /// Received messages that passed sigverify but were not handled
+    /// yet, in arrival order
+    inbox: VecDeque<SignedMessage>,
+
     /// Current view
     current_view: u64,
 
-    /// FIFO queue for recent views
-    recent_views: VecDeque<View>,
-}
+    /// Hash of the genesis block
+    genesis: BlockHash,
+    genesis_config: GenesisConfig,
 
-#[derive(Debug)]
-pub struct View {
-    pub height: u64,
-    pub leader: PublicKey,
-    pub block: Block,
-    pub blockhash: Signature,
+    /// Domain we sign and verify in, from the chain id of the genesis
+    domain: SigningDomain,
+
+    /// Uncommitted blocks above the last committed block
+    block_tree: BlockTree,
+
+    /// Committed blocks, in commit order
+    committed: Vec<BlockNode>,
 }
 
 impl Endpoint {
     pub fn new_genesis(
         identity: Identity,
         peers: Vec<Peer>,
+        genesis_config: GenesisConfig,
     ) -> Endpoint {
-        let mut quorum: Vec<PublicKey> = peers
-            .iter()
-            .map(|p| p.public_key)
+        let peers = peers
+            .into_iter()
+            .map(|peer| (peer.public_key, peer))
             .collect();
-        quorum.push(identity.public_key);
+        let genesis = genesis_config.hash();
         Endpoint {
             identity,
             peers,
-            quorum,
+            refused_peers: vec![],
+            epochs: genesis_config
+                .epochs()
+                .expect("genesis config defines epoch 0"),
+            certificate_cache: CertificateCache::new(
+                CERTIFICATE_CACHE_CAPACITY,
+            ),
+            pending_transactions: vec![],
             self_vote: None,
+            self_new_view: None,
+            high_qc: QuorumCertificate::Genesis,
+            key_share: None,
+            inbox: VecDeque::new(),
             current_view: 0,
-            recent_views: Default::default(),
+            genesis,
+            domain: SigningDomain::new(genesis_config.chain_id),
+            genesis_config,
+            block_tree: BlockTree::new(genesis),
+            committed: vec![],
         }
     }
 
+    /// Our key with a proof of possession, needed to be admitted as a
+    /// validator
+    pub fn registration(&self) -> Result<Registration, SignError> {
+        self.identity.signer.registration()
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.identity.public_key
+    }
+
+    /// Gives us our share of the group key of the genesis epoch, so we
+    /// send partial signatures along with our votes
+    pub fn set_key_share(&mut self, key_share: KeyShare) {
+        self.key_share = Some(key_share);
+    }
+
+    /// Highest QC we know of
+    pub fn high_qc(&self) -> &QuorumCertificate {
+        &self.high_qc
+    }
+
+    /// Queues a transaction for inclusion in our next proposal.
+    /// Transactions that do not verify are dropped, as replicas would
+    /// not vote for a block carrying them. Returns whether it was
+    /// queued.
+    pub fn submit_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> bool {
+        if !transaction.verify(&self.domain) {
+            println!(
+                "{}: dropping invalid transaction",
+                self.identity.name
+            );
+            return false;
+        }
+        self.pending_transactions
+            .push(transaction);
+        true
+    }
+
+    /// Test hook: queues a transaction without verifying it, so that
+    /// we propose it as a byzantine primary would
+    pub fn submit_unverified_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) {
+        self.pending_transactions
+            .push(transaction);
+    }
+
     /// Broadcasts a message to all other peers in the network
     pub fn broadcast(&self, message: SignedMessage) {
-        for peer in &self.peers {
+        for peer in self.peers.values() {
             peer.sender
                 .send(message.clone())
                 .expect("receivers are never dropped in this poc");
@@ -71,28 +183,101 @@ impl Endpoint {
 
     /// Sends a message to specific peer in the network
     pub fn send_to(&self, peer: &PublicKey, message: SignedMessage) {
-        self.peers
-            .iter()
-            .find(|p| p.public_key == *peer)
-            .expect("guaranteed to exist in this poc")
-            .sender
+        let Some(peer) = self.peers.get(peer) else {
+            println!(
+                "{}: not sending to refused peer",
+                self.identity.name
+            );
+            return;
+        };
+        peer.sender
             .send(message)
             .expect("receivers are never dropped in this poc");
     }
 
+    /// Signs a message with our signer. Returns None if it refuses, e.g.
+    /// because it would be a double sign, or cannot be reached.
+    fn sign(&self, message_type: MessageType) -> Option<SignedMessage> {
+        SignedMessage::new(
+            message_type,
+            &self.domain,
+            &self.identity.signer,
+        )
+        .inspect_err(|err| {
+            println!("{}: could not sign: {err}", self.identity.name)
+        })
+        .ok()
+    }
+
+    /// Exchanges genesis hashes with every peer. Peers that answer with
+    /// a different genesis are refused. Peers that do not answer in
+    /// time are kept, as they may just be slow to start.
+    fn handshake(&mut self) {
+        if let Some(handshake) =
+            self.sign(MessageType::Handshake(self.genesis))
+        {
+            self.broadcast(handshake);
+        }
+
+        let mut pending: Vec<PublicKey> =
+            self.peers.keys().copied().collect();
+        let mut refused = vec![];
+        let start_timer = Instant::now();
+        while !pending.is_empty()
+            && start_timer.elapsed().as_millis() <= TIMEOUT_MILLIS
+        {
+            pending.retain(|peer| {
+                let Some(message) = self.try_next_message_from(*peer)
+                else {
+                    return true;
+                };
+                match message.message_type {
+                    MessageType::Handshake(genesis)
+                        if message.transmitter == *peer
+                            && message.verify(&self.domain) =>
+                    {
+                        if genesis != self.genesis {
+                            refused.push(*peer);
+                        }
+                        false
+                    }
+                    _ => {
+                        println!(
+                            "{}: expected handshake",
+                            self.identity.name
+                        );
+                        true
+                    }
+                }
+            });
+        }
+
+        for peer in refused {
+            let peer = self
+                .peers
+                .shift_remove(&peer)
+                .expect("refused peers come from our peer list");
+            println!(
+                "{}: refusing peer with different genesis",
+                self.identity.name
+            );
+            self.refused_peers.push(peer);
+        }
+    }
+
     // Obtain an iterator over all outstanding messages. The iterator
     // filters messages that fail sigverify
     fn _pending_messages<'a>(
         &'a self,
     ) -> impl Iterator<Item = SignedMessage> + 'a {
         self.peers
-            .iter()
+            .values()
             // This is susceptible to DoS if one peer spams faster than
             // we can process.
             .flat_map(|peer| peer.receiver.try_iter())
             // Discard messages that fail verification
             .filter(|msg| {
-                if msg.verify() {
+                if msg.verify(&self.domain) {
                     true
                 } else {
                     println!("message {msg:?} failed sigverify");
@@ -102,21 +287,56 @@ impl Endpoint {
     }
 
     /// Obtain an incoming message if one exists. Messages that fail
-    /// verification are discarded
-    fn next_message(&self) -> Option<SignedMessage> {
-        for peer in &self.peers {
-            // This is susceptible to DoS if one peer spams faster than
-            // we can process. Especially one of the first
-            // peers in our list.
-            if let Ok(msg) = peer.receiver.try_recv() {
-                if msg.verify() {
-                    return Some(msg);
-                } else {
-                    println!("message {msg:?} failed sigverify");
+    /// verification are discarded.
+    ///
+    /// Once every verified message has been handled, all outstanding
+    /// messages from every peer are sigverified together as a batch.
+    fn next_message(&mut self) -> Option<SignedMessage> {
+        if self.inbox.is_empty() {
+            let mut batch = BatchVerifier::new(self.domain);
+            for peer in self.peers.values() {
+                // This is susceptible to DoS if one peer spams faster
+                // than we can process.
+                for msg in peer.receiver.try_iter() {
+                    batch.push(msg);
                 }
             }
+            self.verify_batch(batch);
         }
-        None
+        self.inbox.pop_front()
+    }
+
+    /// Obtain an incoming message signed by a specific peer if one
+    /// exists. Messages that fail verification are discarded.
+    ///
+    /// If none is left, all outstanding messages from that peer are
+    /// sigverified together as a batch. Messages from other peers are
+    /// left for `next_message`.
+    fn next_message_from(
+        &mut self,
+        peer: PublicKey,
+    ) -> Option<SignedMessage> {
+        let from_peer = |msg: &SignedMessage| msg.transmitter == peer;
+        if !self.inbox.iter().any(from_peer) {
+            let mut batch = BatchVerifier::new(self.domain);
+            if let Some(peer) = self.peers.get(&peer) {
+                for msg in peer.receiver.try_iter() {
+                    batch.push(msg);
+                }
+            }
+            self.verify_batch(batch);
+        }
+        let position = self.inbox.iter().position(from_peer)?;
+        self.inbox.remove(position)
+    }
+
+    /// Sigverifies a batch of messages and queues the valid ones
+    fn verify_batch(&mut self, mut batch: BatchVerifier) {
+        let VerifiedBatch { valid, invalid } = batch.verify();
+        for msg in invalid {
+            println!("message {msg:?} failed sigverify");
+        }
+        self.inbox.extend(valid);
     }
 
     /// Check for pending message from a specific peer. Does not verify!
@@ -125,8 +345,7 @@ impl Endpoint {
         peer: PublicKey,
     ) -> Option<SignedMessage> {
         self.peers
-            .iter()
-            .find(|p| p.public_key == peer)
+            .get(&peer)
             .and_then(|peer| peer.receiver.try_recv().ok())
     }
 
@@ -137,12 +356,11 @@ impl Endpoint {
         peer: PublicKey,
     ) -> Option<SignedMessage> {
         self.peers
-            .iter()
-            .find(|p| p.public_key == peer)
+            .get(&peer)
             .and_then(|peer| peer.receiver.recv().ok())
             // Discard message if not valid
             .filter(|msg| {
-                if msg.verify() {
+                if msg.verify(&self.domain) {
                     true
                 } else {
                     println!("message {msg:?} failed sigverify");
@@ -151,14 +369,40 @@ impl Endpoint {
             })
     }
 
-    // Size of quorum
-    fn quorum_size(&self) -> u64 {
-        self.peers.len() as u64 + 1
+    pub fn start_consensus(&mut self) {
+        self.run(200);
+        #[allow(deprecated)]
+        std::thread::sleep_ms(1000);
+        println!("\n");
+        #[allow(deprecated)]
+        std::thread::sleep_ms(1000);
+        println!(
+            "{}; view {}; blockhash {}",
+            self.identity.name,
+            self.current_view,
+            self.latest_blockhash()
+        )
     }
 
-    pub fn start_consensus(&mut self) {
+    /// Runs consensus from genesis through view `views`
+    pub fn run(&mut self, views: u64) {
+        // Wait for the genesis start time
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock is after the unix epoch")
+            .as_secs();
+        if let Some(wait) = self
+            .genesis_config
+            .start_time
+            .checked_sub(now)
+        {
+            std::thread::sleep(Duration::from_secs(wait));
+        }
+
+        self.handshake();
+
         // We start view at 1 because view 0 is genesis
-        for view in 1..=200 {
+        for view in 1..=views {
             self.current_view = view;
 
             println!("{}: current view is {view}", self.identity.name);
@@ -171,24 +415,24 @@ impl Endpoint {
                 Primary::Peer(peer) => self.nonprimary_logic(peer),
             };
         }
-        #[allow(deprecated)]
-        std::thread::sleep_ms(1000);
-        println!("\n");
-        #[allow(deprecated)]
-        std::thread::sleep_ms(1000);
-        println!(
-            "{}; view {}; blockhash {}",
-            self.identity.name,
-            self.current_view,
-            bs58::encode(
-                self.recent_views
-                    .back()
-                    .unwrap()
-                    .blockhash
-                    .as_bytes()
-            )
-            .into_string()
-        )
+    }
+
+    /// Hashes of the blocks we have committed, in commit order
+    pub fn committed(&self) -> Vec<BlockHash> {
+        self.committed
+            .iter()
+            .map(|node| node.blockhash)
+            .collect()
+    }
+
+    /// Blocks we have committed, in commit order
+    pub fn committed_blocks(&self) -> &[BlockNode] {
+        &self.committed
+    }
+
+    /// Validator sets by epoch, as far as we know them
+    pub fn epochs(&self) -> &EpochSchedule {
+        &self.epochs
     }
 
     /// The code to be run for a view when the current node IS a primary
@@ -209,20 +453,52 @@ impl Endpoint {
         let mut new_views_received = Vec::<NewView>::new();
         let mut new_views_received_sigs = Vec::<Signature>::new();
         let mut new_views_received_peers = IndexSet::<PublicKey>::new();
-        let mut votes_received = HashMap::<
-            Vote,
-            (Vec<Signature>, IndexSet<PublicKey>),
-        >::new();
+        let voted_view = self.current_view.saturating_sub(1);
+        let mut votes_received = VoteAggregator::new(
+            self.epochs
+                .validator_set(voted_view)
+                .clone(),
+            self.domain,
+        );
+        let mut partials_received = self
+            .epochs
+            .threshold_keys(voted_view)
+            .map(|keys| {
+                PartialAggregator::new(keys.clone(), self.domain)
+            });
 
         // Check if we have a vote
         if let Some(SignedMessage {
-            message_type: MessageType::Vote(vote),
+            message_type,
             transmitter,
             signature,
         }) = self.self_vote.take()
         {
-            votes_received
-                .insert(vote, (vec![signature], [transmitter].into()));
+            let (message_type, partial) = split_partial(message_type);
+            if let MessageType::Vote(vote) = message_type {
+                self.collect_vote(
+                    &mut votes_received,
+                    &mut partials_received,
+                    vote,
+                    &transmitter,
+                    &signature,
+                    partial,
+                );
+            }
+        }
+
+        // Check if we have a new view
+        if let Some(SignedMessage {
+            message_type: MessageType::NewView(eta),
+            transmitter,
+            signature,
+        }) = self.self_new_view.take()
+        {
+            if eta.view == self.current_view {
+                new_views_received.push(eta);
+                new_views_received_sigs.push(signature);
+                new_views_received_peers.insert(transmitter);
+            }
         }
 
         // TODO: for now we assume a primary cannot be a primary twice
@@ -235,6 +511,7 @@ impl Endpoint {
             'message_loop: loop {
                 // Check if we've timed out
                 if start_timer.elapsed().as_millis() > TIMEOUT_MILLIS {
+                    self.send_new_view();
                     return;
                 }
 
@@ -248,23 +525,33 @@ impl Endpoint {
                     // get a new message
                     continue;
                 };
+                let (message_type, partial) =
+                    split_partial(message_type);
 
                 // BYZANTINE:
                 // We must check that the transmitter in the (verified)
-                // message is a peer in the quorum.
+                // message is a validator in the epoch of the view the
+                // message refers to.
                 //
                 // PERF todo: pubkey check is cheaper than sigverify, so
                 // swap order.
-                let not_in_quorum = self
-                    .peers
-                    .iter()
-                    .find(|p| p.public_key == transmitter)
-                    .is_none();
-                if not_in_quorum {
+                let message_view = match &message_type {
+                    MessageType::Vote(v) => v.view,
+                    MessageType::NewView(eta) => eta.view,
+                    MessageType::Block(block) => block.view,
+                    // Handshakes only happen before consensus starts
+                    MessageType::Handshake(_) => continue,
+                    MessageType::ThresholdVote { .. } => {
+                        unreachable!("partials are split off above")
+                    }
+                };
+                if !self
+                    .epochs
+                    .validator_set(message_view)
+                    .contains(&transmitter)
+                {
                     // Ignore this message
-                    println!(
-                        "received message from peer not in quorum"
-                    );
+                    println!("received message from non-validator");
                     continue;
                 }
 
@@ -280,50 +567,58 @@ impl Endpoint {
                         // probably won't run
                         // behind in this POC.
                         if v.view == self.current_view - 1 {
-                            votes_received
-                                .entry(v)
-                                .and_modify(|(sigs, peers)| {
-                                    if peers.insert(transmitter) {
-                                        sigs.push(signature);
-                                    };
-                                })
-                                .or_insert_with(|| {
-                                    (
-                                        vec![signature],
-                                        [transmitter].into(),
-                                    )
-                                });
-
                             // Check if we have enough votes for qc
-                            let (vote, (sigs, ref mut peers)) = {
-                                let mut most_common_vote = None;
-                                let mut high_count = 0;
-                                for vote in votes_received.iter_mut() {
-                                    if vote.1 .1.len() > high_count {
-                                        high_count = vote.1 .1.len();
-                                        most_common_vote = Some(vote);
+                            if self.collect_vote(
+                                &mut votes_received,
+                                &mut partials_received,
+                                v.clone(),
+                                &transmitter,
+                                &signature,
+                                partial,
+                            ) {
+                                // If so make the qc using vote
+                                // blockhash, preferring a threshold qc
+                                // in epochs with a group key
+                                let qc = match partials_received
+                                    .as_ref()
+                                    .and_then(|partials| {
+                                        partials.certificate(
+                                            &v,
+                                            &self.identity.signer,
+                                        )
+                                    }) {
+                                    Some(qc) => {
+                                        println!(
+                                            "{} building threshold QC",
+                                            self.identity.name
+                                        );
+                                        qc.map(QuorumCertificate::Threshold)
+                                    }
+                                    None => {
+                                        println!(
+                                            "{} building QC",
+                                            self.identity.name
+                                        );
+                                        votes_received
+                                            .certificate(
+                                                &v,
+                                                &self.identity.signer,
+                                            )
+                                            .expect(
+                                                "votes form a quorum",
+                                            )
+                                    }
+                                };
+                                match qc {
+                                    Ok(qc) => break 'message_loop qc,
+                                    Err(err) => {
+                                        println!(
+                                            "{}: could not sign QC: {err}",
+                                            self.identity.name
+                                        );
+                                        return;
                                     }
                                 }
-                                most_common_vote.unwrap()
-                            };
-
-                            if self.is_supermajority(peers.len()) {
-                                // If so make the qc using vote
-                                // blockhash
-                                println!(
-                                    "{} building QC",
-                                    self.identity.name
-                                );
-                                let qc = QuorumCertificate::from_votes(
-                                    vote.clone(),
-                                    &sigs,
-                                    // okay to take because we are
-                                    // discarding everything right
-                                    // after
-                                    core::mem::take(peers),
-                                    &self.identity.private_key,
-                                );
-                                break 'message_loop qc;
                             }
                         }
                     }
@@ -340,6 +635,18 @@ impl Endpoint {
                         // Nodes probably
                         // won't run behind in this POC.
                         if eta.view == self.current_view {
+                            // BYZANTINE:
+                            // A replica could poison the AggQC with a
+                            // bogus high QC, so only valid new views
+                            // count towards the threshold.
+                            if let Err(err) =
+                                eta.verify(&self.verify_context())
+                            {
+                                // TODO: keep proof and blacklist
+                                println!("invalid new view: {err}");
+                                continue;
+                            }
+
                             if new_views_received_peers
                                 .insert(transmitter)
                             {
@@ -349,17 +656,31 @@ impl Endpoint {
 
                             // Check if we have enough votes for aggqc
                             if self.is_supermajority(
+                                self.current_view,
                                 new_views_received_sigs.len(),
                             ) {
                                 println!("building aggQC");
-                                let aggqc =
-                                    QuorumCertificate::from_newviews(
-                                        new_views_received,
-                                        new_views_received_sigs,
-                                        new_views_received_peers,
-                                        &self.identity.private_key,
-                                    );
-                                break 'message_loop aggqc;
+                                match QuorumCertificate::from_newviews(
+                                    new_views_received,
+                                    new_views_received_sigs,
+                                    new_views_received_peers,
+                                    self.epochs.validator_set(
+                                        self.current_view,
+                                    ),
+                                    &self.domain,
+                                    &self.identity.signer,
+                                ) {
+                                    Ok(aggqc) => {
+                                        break 'message_loop aggqc
+                                    }
+                                    Err(err) => {
+                                        println!(
+                                            "{}: could not sign aggQC: {err}",
+                                            self.identity.name
+                                        );
+                                        return;
+                                    }
+                                }
                             }
                         }
                     }
@@ -368,243 +689,212 @@ impl Endpoint {
                         // Should never receive block as primary. Drop.
                         drop(block);
                     }
+
+                    MessageType::Handshake(_) => {
+                        unreachable!("handshakes are skipped above")
+                    }
+
+                    MessageType::ThresholdVote { .. } => {
+                        unreachable!("partials are split off above")
+                    }
                 }
             }
         };
 
+        self.update_high_qc(&certificate);
+
         // Build block with certificate
         let block = Block {
-            transactions: vec![],
-            certificate,
+            transactions: core::mem::take(
+                &mut self.pending_transactions,
+            ),
             view: self.current_view,
-            last_blockhash: self
-                .recent_views
-                .back()
-                .map(|view| view.blockhash)
-                .unwrap_or(Signature(
-                    // Genesis
-                    bls_signatures::PrivateKey::from_bytes(&[0; 32])
-                        .unwrap()
-                        .sign(&[]),
-                )),
+            // Extend the block our certificate certifies
+            last_blockhash: certificate
+                .certified_blockhash()
+                .unwrap_or(self.genesis),
+            certificate,
         };
 
         // Broadcast block
-        let block_message = SignedMessage::block(
-            block.clone(),
-            &self.identity.private_key,
-        );
-        // Add to our views
-        self.recent_views.push_back(View {
-            height: self.current_view,
-            leader: self.identity.public_key,
-            block,
-            blockhash: block_message.signature,
-        });
-
+        let Some(block_message) =
+            self.sign(MessageType::Block(block.clone()))
+        else {
+            return;
+        };
+        // Add to our block tree, and vote and commit through our own
+        // block like the replicas that accept it. Our vote counts
+        // towards the next QC like any other, which the n - f honest
+        // validators need to reach a quorum.
+        let vote = Vote {
+            view: block.view,
+            blockhash: block.hash(),
+        };
+        self.block_tree
+            .insert(block, self.identity.public_key);
         self.broadcast(block_message);
+        self.send_vote(vote.clone());
+        self.commit_through(&vote.blockhash);
     }
 
-    pub fn is_supermajority(&self, num: usize) -> bool {
-        num > 2 * self.quorum_size() as usize / 3
+    /// Folds a vote into `votes` and its partial signature, if any, into
+    /// `partials`. Returns whether the votes for it reached a quorum.
+    ///
+    /// The partial is attributed to the transmitter's index in the
+    /// validator set rather than to an index it claims, so a validator
+    /// cannot submit partials for others.
+    fn collect_vote(
+        &self,
+        votes: &mut VoteAggregator,
+        partials: &mut Option<PartialAggregator>,
+        vote: Vote,
+        transmitter: &PublicKey,
+        signature: &Signature,
+        partial: Option<Signature>,
+    ) -> bool {
+        if let (Some(partials), Some(partial)) = (partials, partial) {
+            if let Some(index) = self
+                .epochs
+                .validator_set(vote.view)
+                .index_of(transmitter)
+            {
+                partials.insert(
+                    vote.clone(),
+                    PartialSignature {
+                        index: index as usize,
+                        signature: partial,
+                    },
+                );
+            }
+        }
+        votes.insert(vote, transmitter, signature)
+    }
+
+    /// Context to verify certificates against, sharing our cache of
+    /// verified certificates
+    fn verify_context(&self) -> VerifyContext<'_> {
+        VerifyContext::new(&self.epochs, self.domain)
+            .with_cache(&self.certificate_cache)
+    }
+
+    /// Whether `num` distinct signers form a supermajority of the
+    /// validator set governing `view`
+    pub fn is_supermajority(&self, view: u64, num: usize) -> bool {
+        self.epochs
+            .validator_set(view)
+            .is_supermajority(num)
     }
 
     /// The code to be run for a view when the current node IS NOT a
     /// primary
     pub fn nonprimary_logic(&mut self, primary: PublicKey) {
         let consensus_result;
+        let accepted;
         println!("{} is running nonprimary logic", self.identity.name);
 
         // We must wait for block from primary
         let start_timer = Instant::now();
         'receive_block_and_vote: loop {
             if start_timer.elapsed().as_millis() > TIMEOUT_MILLIS {
-                return;
+                consensus_result = ConsensusResult::Timeout;
+                accepted = None;
+                break 'receive_block_and_vote;
             }
 
-            let Some(message) = self.try_next_message_from(primary)
-            else {
+            let Some(message) = self.next_message_from(primary) else {
                 continue;
             };
 
-            if !message.verify() {
-                println!("message from primary failed sigverify");
-                continue;
-            }
-
             match message.message_type {
                 MessageType::Block(block) => {
+                    let blockhash = block.hash();
                     println!(
-                        "{}: received block {}",
+                        "{}: received block {blockhash}",
                         self.identity.name,
-                        bs58::encode(message.signature.as_bytes())
-                            .into_string()
                     );
-                    match &block.certificate {
+                    let safe = match &block.certificate {
                         QuorumCertificate::Genesis => {
-                            // Only true if first view
-                            if self.current_view == 1 {
-                                // Send vote to next primary
-                                let signed_vote = SignedMessage::vote(
-                                    Vote {
-                                        view: self.current_view,
-                                        blockhash: block.last_blockhash,
-                                    },
-                                    &self.identity.private_key,
-                                );
-                                self.recent_views.push_back(View {
-                                    height: block.view,
-                                    leader: message.transmitter,
-                                    block: block,
-                                    blockhash: message.signature,
-                                });
-
-                                match self.primary_for_view(
-                                    self.current_view + 1,
-                                ) {
-                                    #[allow(unused_must_use)]
-                                    Primary::OurTurn => {
-                                        // Record self vote
-                                        self.self_vote
-                                            .insert(signed_vote);
-                                    }
-                                    Primary::Peer(next_primary) => {
-                                        // Otherwise send to next
-                                        // primary
-                                        self.send_to(
-                                            &next_primary,
-                                            signed_vote,
-                                        );
-                                    }
-                                }
-
-                                // Success means we sent vote
-                                println!(
-                                    "{}: sent vote",
-                                    self.identity.name,
-                                );
-                                consensus_result =
-                                    ConsensusResult::Success;
-                                break 'receive_block_and_vote;
-                            } else {
+                            // Only true if first view, extending genesis
+                            let extends_genesis = self.current_view
+                                == 1
+                                && block.view == 1
+                                && block.last_blockhash == self.genesis;
+                            if !extends_genesis {
                                 println!("invalid genesis");
                             }
+                            extends_genesis
                         }
 
                         QuorumCertificate::Happy(qc) => {
-                            if qc.valid(&self.quorum) {
-                                if pipeline_safe_block_qc(
+                            match qc.verify(&self.verify_context()) {
+                                Ok(()) => pipeline_safe_block_qc(
                                     &block,
-                                    &qc,
+                                    &qc.vote,
                                     self.current_view,
-                                ) {
-                                    // Send vote to next primary
-                                    let signed_vote =
-                                        SignedMessage::vote(
-                                            Vote {
-                                                view: self.current_view,
-                                                blockhash: block
-                                                    .last_blockhash,
-                                            },
-                                            &self.identity.private_key,
-                                        );
-                                    self.recent_views.push_back(View {
-                                        height: block.view,
-                                        leader: message.transmitter,
-                                        block: block,
-                                        blockhash: message.signature,
-                                    });
-
-                                    match self.primary_for_view(
-                                        self.current_view + 1,
-                                    ) {
-                                        #[allow(unused_must_use)]
-                                        Primary::OurTurn => {
-                                            // Record self vote
-                                            self.self_vote
-                                                .insert(signed_vote);
-                                        }
-                                        Primary::Peer(next_primary) => {
-                                            // Otherwise send to next
-                                            // primary
-                                            self.send_to(
-                                                &next_primary,
-                                                signed_vote,
-                                            );
-                                        }
-                                    }
+                                ),
+                                Err(err) => {
+                                    // TODO: keep proof and blacklist
+                                    println!("invalid qc: {err}");
+                                    false
+                                }
+                            }
+                        }
 
-                                    // Success means we sent vote
+                        QuorumCertificate::Threshold(qc) => {
+                            match qc.verify(&self.verify_context()) {
+                                Ok(()) => pipeline_safe_block_qc(
+                                    &block,
+                                    &qc.vote,
+                                    self.current_view,
+                                ),
+                                Err(err) => {
+                                    // TODO: keep proof and blacklist
                                     println!(
-                                        "{}: sent vote",
-                                        self.identity.name
+                                        "invalid threshold qc: {err}"
                                     );
-                                    consensus_result =
-                                        ConsensusResult::Success;
-                                    break 'receive_block_and_vote;
+                                    false
                                 }
-                            } else {
-                                // TODO: keep proof and blacklist
-                                println!("invalid qc");
                             }
                         }
+
                         QuorumCertificate::Sad(aggqc) => {
-                            if aggqc.valid(&self.quorum) {
-                                if pipeline_safe_block_aggqc(
+                            match aggqc.verify(
+                                block.view,
+                                &self.verify_context(),
+                            ) {
+                                Ok(()) => pipeline_safe_block_aggqc(
                                     &block,
-                                    &aggqc,
+                                    aggqc,
                                     self.current_view,
-                                ) {
-                                    // Send vote to next primary
-                                    let signed_vote =
-                                        SignedMessage::vote(
-                                            Vote {
-                                                view: self.current_view,
-                                                blockhash: block
-                                                    .last_blockhash,
-                                            },
-                                            &self.identity.private_key,
-                                        );
-                                    self.recent_views.push_back(View {
-                                        height: block.view,
-                                        leader: message.transmitter,
-                                        block: block,
-                                        blockhash: message.signature,
-                                    });
-
-                                    match self.primary_for_view(
-                                        self.current_view + 1,
-                                    ) {
-                                        #[allow(unused_must_use)]
-                                        Primary::OurTurn => {
-                                            // Record self vote
-                                            self.self_vote
-                                                .insert(signed_vote);
-                                        }
-                                        Primary::Peer(next_primary) => {
-                                            // Otherwise send to next
-                                            // primary
-                                            self.send_to(
-                                                &next_primary,
-                                                signed_vote,
-                                            );
-                                        }
-                                    }
-
-                                    // Success means we sent vote
-                                    println!(
-                                        "{}: sent vote",
-                                        self.identity.name
-                                    );
-                                    consensus_result =
-                                        ConsensusResult::Success;
-                                    break 'receive_block_and_vote;
+                                    self.genesis,
+                                ),
+                                Err(err) => {
+                                    // TODO: keep proof and blacklist
+                                    println!("invalid aggqc: {err}");
+                                    false
                                 }
-                            } else {
-                                // TODO: keep proof and blacklist
-                                println!("invalid qc");
                             }
                         }
+                    };
+
+                    // BYZANTINE:
+                    // A primary may propose transactions that do not
+                    // verify, e.g. a reconfiguration to no validators,
+                    // which would halt the chain once committed. Never
+                    // vote for such a block.
+                    let valid_transactions =
+                        safe && block.verify_transactions(&self.domain);
+                    if safe && !valid_transactions {
+                        // TODO: keep proof and blacklist
+                        println!("block with invalid transactions");
+                    }
+
+                    if valid_transactions {
+                        self.update_high_qc(&block.certificate);
+                        self.accept_block(block, message.transmitter);
+                        consensus_result = ConsensusResult::Success;
+                        accepted = Some(blockhash);
+                        break 'receive_block_and_vote;
                     }
                 }
 
@@ -617,87 +907,217 @@ impl Endpoint {
 
         match consensus_result {
             ConsensusResult::Success => {
-                let mut rev_iter = self.recent_views.iter().rev();
-
-                let mut block_grandparent: Option<&View> = None;
-                let commit_through_grandparent = match (
-                    rev_iter.next(),
-                    rev_iter.next(),
-                    rev_iter.next(),
-                ) {
-                    (Some(latest), Some(parent), Some(grandparent)) => {
-                        block_grandparent = Some(grandparent);
-                        let direct_parent = latest.block.last_blockhash
-                            == parent.blockhash;
-                        let direct_grandparent =
-                            parent.block.last_blockhash
-                                == grandparent.blockhash;
-
-                        // commit through grandparent
-                        direct_parent & direct_grandparent
-                    }
-                    _ => {
-                        // not enough blocks
-                        false
-                    }
-                };
-
-                if commit_through_grandparent {
-                    let grandparent =
-                        block_grandparent.unwrap().blockhash;
-                    while let Some(block) =
-                        self.recent_views.pop_front()
-                    {
-                        let stop = block.blockhash == grandparent;
-                        self.execute(block);
-                        if stop {
-                            break;
-                        }
-                    }
-                }
+                let accepted = accepted
+                    .expect("success means a block was accepted");
+                self.commit_through(&accepted);
             }
             ConsensusResult::Timeout => {
-                //
+                // Let the next primary build an AggQC
+                self.send_new_view();
             }
         }
     }
 
-    /// Deterministic function that determines primary from view
+    /// Keeps the QC certified by `certificate` if it is higher than our
+    /// high QC
+    fn update_high_qc(&mut self, certificate: &QuorumCertificate) {
+        let qc = match certificate {
+            QuorumCertificate::Happy(_)
+            | QuorumCertificate::Threshold(_) => certificate.clone(),
+            QuorumCertificate::Sad(aggqc) => match aggqc.find_high_qc()
+            {
+                Some(high_qc) => high_qc.clone().into(),
+                None => return,
+            },
+            QuorumCertificate::Genesis => return,
+        };
+        let view = |certificate: &QuorumCertificate| {
+            certificate
+                .certified_vote()
+                .map_or(0, |vote| vote.view)
+        };
+        if view(&qc) > view(&self.high_qc) {
+            self.high_qc = qc;
+        }
+    }
+
+    /// Sends our high QC to the primary of the next view after the
+    /// current one failed, so that it can build an AggQC
+    fn send_new_view(&mut self) {
+        let next_view = self.current_view + 1;
+
+        // Nodes that are not (yet) validators do not take part
+        if !self
+            .epochs
+            .validator_set(next_view)
+            .contains(&self.identity.public_key)
+        {
+            return;
+        }
+
+        let Some(new_view) = self.sign(MessageType::NewView(NewView {
+            view: next_view,
+            certificate: self.high_qc.clone(),
+        })) else {
+            return;
+        };
+        match self.primary_for_view(next_view) {
+            Primary::OurTurn => self.self_new_view = Some(new_view),
+            Primary::Peer(next_primary) => {
+                self.send_to(&next_primary, new_view)
+            }
+        }
+        println!("{}: sent new view", self.identity.name);
+    }
+
+    /// Records a block that passed the safety checks and, if we are a
+    /// validator for its view, sends our vote to the next primary.
+    fn accept_block(&mut self, block: Block, leader: PublicKey) {
+        // Vote for the proposed block itself, so the next primary's QC
+        // unambiguously certifies it
+        let vote = Vote {
+            view: block.view,
+            blockhash: block.hash(),
+        };
+        self.block_tree.insert(block, leader);
+        self.send_vote(vote);
+    }
+
+    /// Sends our vote to the next primary if we are a validator for the
+    /// current view
+    fn send_vote(&mut self, vote: Vote) {
+        // Nodes that are not (yet) validators follow the chain without
+        // voting
+        if !self
+            .epochs
+            .validator_set(self.current_view)
+            .contains(&self.identity.public_key)
+        {
+            return;
+        }
+
+        // In epochs with a group key we also sign with our share, if
+        // it was dealt for that key
+        let partial = self
+            .key_share
+            .as_ref()
+            .filter(|share| {
+                self.epochs
+                    .threshold_keys(vote.view)
+                    .is_some_and(|keys| keys.holds(share))
+            })
+            .map(|share| {
+                share
+                    .sign_vote(&vote, &self.domain)
+                    .signature
+            });
+        let message_type = match partial {
+            Some(partial) => {
+                MessageType::ThresholdVote { vote, partial }
+            }
+            None => MessageType::Vote(vote),
+        };
+
+        let Some(signed_vote) = self.sign(message_type) else {
+            return;
+        };
+
+        match self.primary_for_view(self.current_view + 1) {
+            Primary::OurTurn => {
+                // Record self vote
+                self.self_vote = Some(signed_vote);
+            }
+            Primary::Peer(next_primary) => {
+                // Otherwise send to next primary
+                self.send_to(&next_primary, signed_vote);
+            }
+        }
+
+        // Success means we sent vote
+        println!("{}: sent vote", self.identity.name);
+    }
+
+    /// Deterministic function that determines primary from view. The
+    /// leader schedule is taken from the epoch governing the view.
     pub fn primary_for_view(&self, view: u64) -> Primary {
-        // Determine primary index
-        let primary_index = view % self.quorum_size();
+        let primary = *self
+            .epochs
+            .validator_set(view)
+            .leader(view);
 
-        if primary_index == self.identity.index {
-            // If primary index == our index, we are primary
-            return Primary::OurTurn;
+        if primary == self.identity.public_key {
+            Primary::OurTurn
         } else {
-            // Otherwise, find primary
-            for (peer, idx) in self
-                .peers
-                .iter()
-                // Skip over our index
-                .zip((0..).filter(|i| *i != self.identity.index))
-            {
-                if idx == primary_index {
-                    return Primary::Peer(peer.public_key);
-                }
-            }
+            Primary::Peer(primary)
+        }
+    }
+
+    /// Hash of the highest known block
+    fn latest_blockhash(&self) -> BlockHash {
+        self.block_tree
+            .highest()
+            .map(|node| node.blockhash)
+            .unwrap_or(self.block_tree.root())
+    }
 
-            unreachable!(
-                "early return from loop guaranteed: {}",
-                primary_index
-            )
+    /// Commits what the two-chain rule commits once the block with
+    /// hash `latest` is known
+    fn commit_through(&mut self, latest: &BlockHash) {
+        let Some(committed) = self.block_tree.commit_candidate(latest)
+        else {
+            return;
+        };
+        let commit_view = self
+            .block_tree
+            .get(latest)
+            .expect("commit candidates come from known blocks")
+            .view();
+        for node in self.block_tree.commit(&committed) {
+            self.execute(node, commit_view);
         }
     }
 
-    fn execute(&self, grandparent: View) {
+    /// Applies a block committed through the block for `commit_view`
+    fn execute(&mut self, grandparent: BlockNode, commit_view: u64) {
         println!(
             "{} committing block {} at height {}",
             self.identity.name,
-            bs58::encode(grandparent.blockhash.as_bytes())
-                .into_string(),
-            grandparent.height
+            grandparent.blockhash,
+            grandparent.view()
         );
+        // Apply reconfigurations. Every honest node commits the same
+        // block through the same block, so the next epoch is scheduled
+        // at the same view everywhere.
+        for transaction in &grandparent.block.transactions {
+            if let Transaction::Reconfiguration(reconfiguration) =
+                transaction
+            {
+                let Some(validator_set) =
+                    ValidatorSet::from_registrations(
+                        reconfiguration
+                            .validators
+                            .iter()
+                            .copied(),
+                    )
+                else {
+                    // Unreachable for blocks we voted for, as their
+                    // transactions were verified
+                    println!("invalid reconfiguration");
+                    continue;
+                };
+                let epoch = self
+                    .epochs
+                    .schedule(commit_view, validator_set);
+                println!(
+                    "{} scheduled epoch {} with {} validators at view {}",
+                    self.identity.name,
+                    epoch.number,
+                    epoch.validator_set.len(),
+                    epoch.start_view,
+                );
+            }
+        }
+        self.committed.push(grandparent);
     }
 }
 
@@ -721,37 +1141,116 @@ pub struct Peer {
 
 pub struct Identity {
     pub name: &'static str,
-    pub private_key: PrivateKey,
+
+    /// Signs on our behalf
+    pub signer: IdentitySigner,
     pub public_key: PublicKey,
-    /// Deterministic index determined by sorting. Can be
-    /// lexicographically, bytewise, or by another sorting key such
-    /// as stake weight.
-    pub index: u64,
+}
+
+impl Identity {
+    /// Identity whose key is kept in process memory
+    pub fn local(
+        name: &'static str,
+        private_key: PrivateKey,
+    ) -> Identity {
+        Identity {
+            name,
+            public_key: private_key.public_key(),
+            signer: IdentitySigner::Local(private_key),
+        }
+    }
+
+    /// Identity whose key is kept by a separate signer process
+    #[cfg(unix)]
+    pub fn remote(
+        name: &'static str,
+        signer: RemoteSigner,
+    ) -> Identity {
+        Identity {
+            name,
+            public_key: signer.signer_public_key(),
+            signer: IdentitySigner::Remote(signer),
+        }
+    }
+}
+
+/// Where the key of an identity is kept
+pub enum IdentitySigner {
+    Local(PrivateKey),
+
+    /// Behind a signer process with double-sign protection
+    #[cfg(unix)]
+    Remote(RemoteSigner),
+}
+
+impl ConsensusSigner for IdentitySigner {
+    fn signer_public_key(&self) -> PublicKey {
+        match self {
+            IdentitySigner::Local(private_key) => {
+                private_key.signer_public_key()
+            }
+            #[cfg(unix)]
+            IdentitySigner::Remote(signer) => {
+                signer.signer_public_key()
+            }
+        }
+    }
+
+    fn sign_request(
+        &self,
+        request: &SignRequest,
+        domain: &SigningDomain,
+    ) -> Result<Signature, SignError> {
+        match self {
+            IdentitySigner::Local(private_key) => {
+                private_key.sign_request(request, domain)
+            }
+            #[cfg(unix)]
+            IdentitySigner::Remote(signer) => {
+                signer.sign_request(request, domain)
+            }
+        }
+    }
+}
+
+/// Separates the partial signature from a threshold vote, leaving a
+/// plain vote. Other messages are returned as they are.
+fn split_partial(
+    message_type: MessageType,
+) -> (MessageType, Option<Signature>) {
+    match message_type {
+        MessageType::ThresholdVote { vote, partial } => {
+            (MessageType::Vote(vote), Some(partial))
+        }
+        message_type => (message_type, None),
+    }
 }
 
 fn pipeline_safe_block_qc(
     block: &Block,
-    qc: &QC,
+    vote: &Vote,
     current_view: u64,
 ) -> bool {
     // new block
     block.view >= current_view
-        //and directly follows block qc points to
-        && block.view == qc.vote.view + 1
+        // and directly follows the block the qc certifies
+        && block.view == vote.view + 1
+        && block.last_blockhash == vote.blockhash
 }
 
 fn pipeline_safe_block_aggqc(
     block: &Block,
     qc: &AggQC,
     current_view: u64,
+    genesis: BlockHash,
 ) -> bool {
     // new block
     block.view >= current_view
-        // and extends block qc points to
+        // and extends the block the high qc certifies (genesis if every
+        // new view carried the genesis certificate)
         && block.last_blockhash
             == qc
                 .find_high_qc()
-                .unwrap()
-                .vote
-                .blockhash
+                .map(|high_qc| high_qc.vote().blockhash)
+                .unwrap_or(genesis)
 }
@@ -0,0 +1,145 @@
+use crate::{
+    threshold::{ThresholdKeys, ThresholdKeysError},
+    validator_set::ValidatorSet,
+};
+
+/// Number of views between the view in which a reconfiguration commits
+/// and the first view of the epoch it defines, so the new set never
+/// takes effect in a view that is already running.
+pub const EPOCH_ACTIVATION_DELAY: u64 = 10;
+
+#[derive(Debug, Clone)]
+pub struct Epoch {
+    /// Sequential epoch number. Genesis is epoch 0.
+    pub number: u64,
+
+    /// First view governed by this epoch's validator set
+    pub start_view: u64,
+
+    pub validator_set: ValidatorSet,
+
+    /// Group key dealt for the validator set, if QCs of this epoch are
+    /// threshold signed
+    pub threshold_keys: Option<ThresholdKeys>,
+}
+
+/// Ordered history of epochs known to a node. Old epochs are retained
+/// so certificates from past views can still be verified against the
+/// validator set that produced them.
+#[derive(Debug, Clone)]
+pub struct EpochSchedule {
+    /// Epochs sorted by (strictly increasing) start view
+    epochs: Vec<Epoch>,
+}
+
+impl EpochSchedule {
+    pub fn new(genesis_set: ValidatorSet) -> EpochSchedule {
+        EpochSchedule {
+            epochs: vec![Epoch {
+                number: 0,
+                start_view: 0,
+                validator_set: genesis_set,
+                threshold_keys: None,
+            }],
+        }
+    }
+
+    /// Has the QCs of the genesis epoch threshold signed by `keys`,
+    /// which must have been dealt for the genesis set. Keys with one
+    /// share per validator and at least the quorum threshold of the set
+    /// are accepted; that they were dealt for these very validators
+    /// cannot be checked.
+    pub fn with_threshold_keys(
+        mut self,
+        keys: ThresholdKeys,
+    ) -> Result<EpochSchedule, ThresholdKeysError> {
+        let validator_set = &self.epochs[0].validator_set;
+        if keys.share_public_keys.len() != validator_set.len() {
+            return Err(ThresholdKeysError::ShareCountMismatch {
+                shares: keys.share_public_keys.len(),
+                validators: validator_set.len(),
+            });
+        }
+        if keys.threshold < validator_set.quorum_threshold() {
+            return Err(ThresholdKeysError::ThresholdBelowQuorum {
+                threshold: keys.threshold,
+                quorum: validator_set.quorum_threshold(),
+            });
+        }
+        self.epochs[0].threshold_keys = Some(keys);
+        Ok(self)
+    }
+
+    /// The epoch governing a view
+    pub fn epoch_for_view(&self, view: u64) -> &Epoch {
+        self.epochs
+            .iter()
+            .rev()
+            .find(|epoch| epoch.start_view <= view)
+            .expect("genesis epoch starts at view 0")
+    }
+
+    /// The validator set governing a view
+    pub fn validator_set(&self, view: u64) -> &ValidatorSet {
+        &self.epoch_for_view(view).validator_set
+    }
+
+    /// Group key of the epoch governing a view, if its QCs are
+    /// threshold signed
+    pub fn threshold_keys(&self, view: u64) -> Option<&ThresholdKeys> {
+        self.epoch_for_view(view)
+            .threshold_keys
+            .as_ref()
+    }
+
+    /// Schedules the next epoch after a reconfiguration committed
+    /// through the block for `commit_view`, i.e. the block whose
+    /// certificate completed the two-chain committing it. The new set
+    /// takes effect at `commit_view + EPOCH_ACTIVATION_DELAY`. Returns
+    /// the new epoch.
+    ///
+    /// The activation is counted from the commit rather than from the
+    /// block carrying the reconfiguration, as timeouts can delay the
+    /// commit by any number of views. A node commits through a block
+    /// while running its view, so the new epoch always starts in the
+    /// future. A node that missed that block commits through a later
+    /// one and would schedule the epoch later; as elsewhere in this
+    /// poc, nodes are assumed not to fall behind.
+    ///
+    /// Reconfigurations committed through different blocks stack: each
+    /// one schedules another epoch, even while the previous one has not
+    /// started yet, since its views may already be running. Only a
+    /// later reconfiguration committed through the same block replaces
+    /// an epoch, as both would start at the same view.
+    ///
+    /// Nobody deals a group key for the new set, so the new epoch uses
+    /// aggregate QCs only.
+    pub fn schedule(
+        &mut self,
+        commit_view: u64,
+        validator_set: ValidatorSet,
+    ) -> &Epoch {
+        let start_view = commit_view + EPOCH_ACTIVATION_DELAY;
+
+        // Blocks commit through blocks of increasing views, so only an
+        // epoch scheduled through the same block can start at this
+        // view. The last reconfiguration committed through it wins.
+        while self
+            .epochs
+            .last()
+            .is_some_and(|epoch| epoch.start_view >= start_view)
+            && self.epochs.len() > 1
+        {
+            self.epochs.pop();
+        }
+
+        let number = self.epochs.last().unwrap().number + 1;
+        self.epochs.push(Epoch {
+            number,
+            start_view,
+            validator_set,
+            threshold_keys: None,
+        });
+        self.epochs.last().unwrap()
+    }
+}
@@ -0,0 +1,99 @@
+use borsh::BorshSerialize;
+
+use crate::{
+    block::{digest, Block, BlockHash},
+    certificates::QuorumCertificate,
+    crypto::Registration,
+    epoch::EpochSchedule,
+    threshold::{ThresholdKeys, ThresholdKeysError},
+    validator_set::ValidatorSet,
+};
+
+/// Everything needed to derive the genesis block. Every node must be
+/// started from an identical config; nodes with a different genesis
+/// are refused during the handshake.
+#[derive(Debug, Clone, BorshSerialize)]
+pub struct GenesisConfig {
+    /// Identifier distinguishing this chain from others
+    pub chain_id: u64,
+
+    /// Validators of epoch 0, with their proofs of possession
+    pub validators: Vec<Registration>,
+
+    /// Opaque initial application state
+    pub initial_state: Vec<u8>,
+
+    /// Unix timestamp (seconds) at which view 1 may begin
+    pub start_time: u64,
+
+    /// Group key dealt for the validators of epoch 0, if its QCs are
+    /// threshold signed, see `threshold`
+    pub threshold_keys: Option<ThresholdKeys>,
+}
+
+impl GenesisConfig {
+    /// The genesis block (view 0). It carries no transactions and its
+    /// parent hash is the digest of this config, so its hash commits to
+    /// every field above.
+    pub fn genesis_block(&self) -> Block {
+        Block {
+            transactions: vec![],
+            certificate: QuorumCertificate::Genesis,
+            last_blockhash: BlockHash(digest(self)),
+            view: 0,
+        }
+    }
+
+    /// Validator set of epoch 0. Returns None if there are no
+    /// validators or any validator's proof of possession is invalid.
+    pub fn validator_set(&self) -> Option<ValidatorSet> {
+        ValidatorSet::from_registrations(
+            self.validators.iter().copied(),
+        )
+    }
+
+    /// Epochs known at genesis, i.e. epoch 0 with its group key if
+    /// there is one
+    pub fn epochs(&self) -> Result<EpochSchedule, GenesisError> {
+        let epochs = EpochSchedule::new(
+            self.validator_set()
+                .ok_or(GenesisError::InvalidValidatorSet)?,
+        );
+        match self.threshold_keys.clone() {
+            Some(keys) => epochs
+                .with_threshold_keys(keys)
+                .map_err(GenesisError::ThresholdKeys),
+            None => Ok(epochs),
+        }
+    }
+
+    /// Hash of the genesis block
+    pub fn hash(&self) -> BlockHash {
+        self.genesis_block().hash()
+    }
+}
+
+/// Reason a genesis config does not define epoch 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenesisError {
+    /// There are no validators, or a proof of possession is invalid
+    InvalidValidatorSet,
+
+    /// The group key does not fit the validator set
+    ThresholdKeys(ThresholdKeysError),
+}
+
+impl std::fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenesisError::InvalidValidatorSet => {
+                f.write_str("invalid genesis validator set")
+            }
+            GenesisError::ThresholdKeys(error) => {
+                write!(f, "invalid genesis threshold keys: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenesisError {}
@@ -0,0 +1,328 @@
+//! Validator keys on disk, so that a validator keeps its identity
+//! across restarts.
+//!
+//! A key file holds the 32 byte seed the private key is derived from
+//! (see `Signer::from_seed`) and the bs58 encoding of its public key,
+//! which can be exported without the passphrase. The seed is either
+//! stored as is or encrypted with a passphrase: PBKDF2-HMAC-SHA256
+//! derives a key from it, which encrypts the seed with
+//! ChaCha20-Poly1305. The rest of the file is authenticated along with
+//! the seed.
+//!
+//! Key files are created readable by their owner only, and on unix
+//! files that are accessible to anyone else are refused.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, KeyInit};
+use hmac::Hmac;
+use rand::{thread_rng, Rng};
+use sha2::Sha256;
+
+use crate::{
+    crypto::{PrivateKey, Signer},
+    endpoint::Identity,
+};
+
+/// Version of the key file format
+const VERSION: u8 = 1;
+
+/// PBKDF2 iterations for newly encrypted key files. Files record the
+/// count they were written with.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Most PBKDF2 iterations accepted from a key file, so that a corrupt
+/// or hostile file cannot stall the validator
+const MAX_PBKDF2_ITERATIONS: u32 = 10 * PBKDF2_ITERATIONS;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+struct KeyFile {
+    version: u8,
+
+    /// bs58 encoding of the public key
+    public_key: String,
+    secret: Secret,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+enum Secret {
+    Plain {
+        seed: [u8; 32],
+    },
+    Encrypted {
+        salt: [u8; 16],
+        iterations: u32,
+        nonce: [u8; 12],
+        ciphertext: [u8; 32],
+
+        /// Poly1305 tag over the ciphertext and the rest of the file
+        tag: [u8; 16],
+    },
+}
+
+/// Reason a key file could not be written or loaded
+#[derive(Debug)]
+pub enum KeystoreError {
+    Io(std::io::Error),
+
+    /// The key file is accessible to users other than its owner
+    InsecurePermissions {
+        mode: u32,
+    },
+
+    /// The key file could not be decoded
+    Malformed,
+
+    UnsupportedVersion(u8),
+
+    /// The key file asks for no or too many PBKDF2 iterations
+    UnsupportedIterations(u32),
+
+    /// The key file is encrypted but no passphrase was given
+    PassphraseRequired,
+
+    /// The passphrase does not decrypt the key file, or the file was
+    /// tampered with
+    WrongPassphrase,
+
+    /// The key does not match the public key recorded next to it
+    PublicKeyMismatch,
+}
+
+impl std::fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeystoreError::Io(err) => write!(f, "{err}"),
+            KeystoreError::InsecurePermissions { mode } => {
+                write!(
+                    f,
+                    "key file has permissions {mode:o}, expected 600"
+                )
+            }
+            KeystoreError::Malformed => {
+                f.write_str("malformed key file")
+            }
+            KeystoreError::UnsupportedVersion(version) => {
+                write!(f, "unsupported key file version {version}")
+            }
+            KeystoreError::UnsupportedIterations(iterations) => write!(
+                f,
+                "unsupported number of key derivation iterations \
+                 {iterations}"
+            ),
+            KeystoreError::PassphraseRequired => f.write_str(
+                "key file is encrypted, passphrase required",
+            ),
+            KeystoreError::WrongPassphrase => {
+                f.write_str("wrong passphrase")
+            }
+            KeystoreError::PublicKeyMismatch => {
+                f.write_str("key does not match its public key")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeystoreError {}
+
+impl From<std::io::Error> for KeystoreError {
+    fn from(err: std::io::Error) -> Self {
+        KeystoreError::Io(err)
+    }
+}
+
+/// Generates a new key into a file at `path`, encrypted if a
+/// passphrase is given, and returns it. Never overwrites an existing
+/// file.
+pub fn generate(
+    path: impl AsRef<Path>,
+    passphrase: Option<&str>,
+) -> Result<PrivateKey, KeystoreError> {
+    let seed: [u8; 32] = thread_rng().gen();
+    let private_key = PrivateKey::from_seed(seed);
+    let public_key = private_key.public_key().to_string();
+    let secret = match passphrase {
+        None => Secret::Plain { seed },
+        Some(passphrase) => {
+            let salt: [u8; 16] = thread_rng().gen();
+            encrypt(
+                seed,
+                passphrase,
+                &public_key,
+                salt,
+                PBKDF2_ITERATIONS,
+            )
+        }
+    };
+    let key_file = KeyFile {
+        version: VERSION,
+        public_key,
+        secret,
+    };
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(&borsh::to_vec(&key_file)?)?;
+    file.sync_all()?;
+    Ok(private_key)
+}
+
+/// Loads the key stored at `path`, decrypting it with `passphrase` if
+/// it is encrypted
+pub fn load(
+    path: impl AsRef<Path>,
+    passphrase: Option<&str>,
+) -> Result<PrivateKey, KeystoreError> {
+    let key_file = read(path)?;
+    let seed = key_file
+        .secret
+        .seed(passphrase, &key_file.public_key)?;
+
+    let private_key = PrivateKey::from_seed(seed);
+    if private_key.public_key().to_string() != key_file.public_key {
+        return Err(KeystoreError::PublicKeyMismatch);
+    }
+    Ok(private_key)
+}
+
+/// Loads the identity of a validator from its key file
+pub fn load_identity(
+    path: impl AsRef<Path>,
+    name: &'static str,
+    passphrase: Option<&str>,
+) -> Result<Identity, KeystoreError> {
+    Ok(Identity::local(name, load(path, passphrase)?))
+}
+
+/// bs58 encoding of the public key stored at `path`. Does not need the
+/// passphrase.
+pub fn export_public_key(
+    path: impl AsRef<Path>,
+) -> Result<String, KeystoreError> {
+    Ok(read(path)?.public_key)
+}
+
+fn read(path: impl AsRef<Path>) -> Result<KeyFile, KeystoreError> {
+    let mut file = File::open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = file.metadata()?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Err(KeystoreError::InsecurePermissions { mode });
+        }
+    }
+
+    let mut bytes = vec![];
+    file.read_to_end(&mut bytes)?;
+    // Check the version first, other versions may not decode
+    match bytes.first() {
+        Some(&VERSION) => {}
+        Some(&version) => {
+            return Err(KeystoreError::UnsupportedVersion(version))
+        }
+        None => return Err(KeystoreError::Malformed),
+    }
+    borsh::from_slice(&bytes).map_err(|_| KeystoreError::Malformed)
+}
+
+fn encrypt(
+    seed: [u8; 32],
+    passphrase: &str,
+    public_key: &str,
+    salt: [u8; 16],
+    iterations: u32,
+) -> Secret {
+    let nonce: [u8; 12] = thread_rng().gen();
+    let mut ciphertext = seed;
+    let tag = cipher(passphrase, &salt, iterations)
+        .encrypt_in_place_detached(
+            &nonce.into(),
+            &associated_data(public_key, &salt, iterations),
+            &mut ciphertext,
+        )
+        .expect("a seed is far below the size limit of the cipher");
+    Secret::Encrypted {
+        salt,
+        iterations,
+        nonce,
+        ciphertext,
+        tag: tag.into(),
+    }
+}
+
+impl Secret {
+    /// The seed, decrypted with `passphrase` if it is encrypted.
+    /// `public_key` is the one recorded next to the secret.
+    fn seed(
+        &self,
+        passphrase: Option<&str>,
+        public_key: &str,
+    ) -> Result<[u8; 32], KeystoreError> {
+        match self {
+            Secret::Plain { seed } => Ok(*seed),
+            Secret::Encrypted {
+                salt,
+                iterations,
+                nonce,
+                ciphertext,
+                tag,
+            } => {
+                let passphrase = passphrase
+                    .ok_or(KeystoreError::PassphraseRequired)?;
+                // Refuse before deriving the key, as the file could
+                // otherwise keep us busy for hours
+                if !(1..=MAX_PBKDF2_ITERATIONS).contains(iterations) {
+                    return Err(KeystoreError::UnsupportedIterations(
+                        *iterations,
+                    ));
+                }
+                let mut seed = *ciphertext;
+                cipher(passphrase, salt, *iterations)
+                    .decrypt_in_place_detached(
+                        nonce.into(),
+                        &associated_data(public_key, salt, *iterations),
+                        &mut seed,
+                        tag.into(),
+                    )
+                    .map_err(|_| KeystoreError::WrongPassphrase)?;
+                Ok(seed)
+            }
+        }
+    }
+}
+
+/// Everything stored next to the encrypted seed, authenticated along
+/// with it so that none of it can be altered
+fn associated_data(
+    public_key: &str,
+    salt: &[u8; 16],
+    iterations: u32,
+) -> Vec<u8> {
+    borsh::to_vec(&(VERSION, public_key, salt, iterations))
+        .expect("serializing to a vec never fails")
+}
+
+/// ChaCha20-Poly1305 keyed with PBKDF2-HMAC-SHA256 of the passphrase
+fn cipher(
+    passphrase: &str,
+    salt: &[u8; 16],
+    iterations: u32,
+) -> ChaCha20Poly1305 {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(
+        passphrase.as_bytes(),
+        salt,
+        iterations,
+        &mut key,
+    );
+    ChaCha20Poly1305::new(&key.into())
+}
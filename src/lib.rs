@@ -1,9 +1,22 @@
 pub mod cluster;
 pub mod endpoint;
 
+pub mod batch_verifier;
 pub mod block;
+pub mod block_tree;
+pub mod certificate_cache;
 pub mod certificates;
+pub mod domain;
+pub mod epoch;
+pub mod genesis;
+pub mod keystore;
 pub mod message;
+#[cfg(unix)]
+pub mod remote_signer;
+pub mod signer;
+pub mod threshold;
 pub mod transaction;
+pub mod validator_set;
+pub mod vote_aggregator;
 
 pub mod crypto;
@@ -1,17 +1,50 @@
-use bls_signatures::{verify_messages, PrivateKey, Serialize};
-use borsh::BorshSerialize;
+use borsh::{BorshDeserialize, BorshSerialize};
 
 use crate::{
-    block::Block,
-    certificates::QuorumCertificate,
-    crypto::{PublicKey, Signature},
+    block::{Block, BlockHash},
+    certificates::{
+        CertificateError, QuorumCertificate, VerifyContext,
+    },
+    crypto::{PrivateKey, PublicKey, Signature, Signer, Verifier},
+    domain::{SignatureKind, SigningDomain},
+    signer::{ConsensusSigner, SignError, SignRequest},
 };
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, BorshSerialize, Clone)]
 pub enum MessageType {
     Vote(Vote),
+
+    /// Vote along with a partial signature over it by our share of the
+    /// group key, in epochs with threshold QCs. Only the vote is signed
+    /// by the transmitter, as the partial is checked against the share.
+    ThresholdVote {
+        vote: Vote,
+        partial: Signature,
+    },
+
     NewView(NewView),
     Block(Block),
+
+    /// Hash of the sender's genesis block, exchanged before consensus
+    /// starts so that peers on a different chain can be refused
+    Handshake(BlockHash),
+}
+
+impl MessageType {
+    /// Bytes signed for this message, i.e. the serialized message
+    /// under the signing domain of its kind. New views only sign their
+    /// `NewViewSummary` so that the signatures can be aggregated into a
+    /// compact AggQC without the certificates, and blocks only sign
+    /// their header, which commits to the rest of the block.
+    ///
+    /// Nothing signer specific is included: validators register proofs
+    /// of possession, so signatures over the same message (e.g. the same
+    /// vote) can be safely aggregated and verified against the
+    /// aggregated key.
+    pub fn signed_payload(&self, domain: &SigningDomain) -> [u8; 32] {
+        SignRequest::from(self).signing_bytes(domain)
+    }
 }
 
 #[derive(Debug, BorshSerialize, Hash, PartialEq, Eq, Clone)]
@@ -20,10 +53,89 @@ pub struct NewView {
     pub certificate: QuorumCertificate,
 }
 
-#[derive(Clone, Debug, BorshSerialize, Hash, PartialEq, Eq)]
+impl NewView {
+    /// View certified by the sender's high QC, 0 for genesis
+    pub fn high_qc_view(&self) -> u64 {
+        self.certificate
+            .certified_vote()
+            .map_or(0, |vote| vote.view)
+    }
+
+    /// The part of a new view that is signed
+    pub fn summary(&self) -> NewViewSummary {
+        NewViewSummary {
+            view: self.view,
+            high_qc_view: self.high_qc_view(),
+        }
+    }
+
+    /// A new view is valid if it carries the sender's high QC, i.e.
+    /// 1) the genesis certificate or a (happy or threshold) QC, never
+    ///    an AggQC
+    /// 2) certifying a view strictly below the view being entered
+    /// 3) that is itself valid for the epoch of the certified view
+    pub fn verify(
+        &self,
+        ctx: &VerifyContext,
+    ) -> Result<(), CertificateError> {
+        match &self.certificate {
+            QuorumCertificate::Genesis => Ok(()),
+            QuorumCertificate::Happy(qc)
+                if qc.vote.view >= self.view =>
+            {
+                Err(CertificateError::StaleView {
+                    expected: self.view.saturating_sub(1),
+                    found: qc.vote.view,
+                })
+            }
+            QuorumCertificate::Happy(qc) => qc.verify(ctx),
+            QuorumCertificate::Threshold(qc)
+                if qc.vote.view >= self.view =>
+            {
+                Err(CertificateError::StaleView {
+                    expected: self.view.saturating_sub(1),
+                    found: qc.vote.view,
+                })
+            }
+            QuorumCertificate::Threshold(qc) => qc.verify(ctx),
+            QuorumCertificate::Sad(_) => {
+                Err(CertificateError::MissingHighQc)
+            }
+        }
+    }
+}
+
+/// What a replica signs when entering a view: the view and the view of
+/// its high QC. The QC itself is self-certifying, so it does not need
+/// to be signed and the primary only has to forward the highest one.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    BorshSerialize,
+    BorshDeserialize,
+    Hash,
+    PartialEq,
+    Eq,
+)]
+pub struct NewViewSummary {
+    pub view: u64,
+    pub high_qc_view: u64,
+}
+
+impl NewViewSummary {
+    /// Bytes signed by the sender of a new view with this summary
+    pub fn signed_payload(&self, domain: &SigningDomain) -> [u8; 32] {
+        domain.signing_bytes(SignatureKind::NewView, self)
+    }
+}
+
+#[derive(
+    Clone, Debug, BorshSerialize, BorshDeserialize, Hash, PartialEq, Eq,
+)]
 pub struct Vote {
     pub view: u64,
-    pub blockhash: Signature,
+    pub blockhash: BlockHash,
 }
 
 #[derive(Debug, Clone)]
@@ -39,46 +151,81 @@ pub struct SignedMessage {
 }
 
 impl SignedMessage {
-    /// Verifies signature for the serialized form of the message. In
-    /// this poc, messages are always prepended with the transmitter's
-    /// publickey due to a possible rogue key attack in the underlying
-    /// cryptographic scheme used that requires all messages to be
-    /// unique for an aggregated signature.
-    pub fn verify(&self) -> bool {
-        // Construct signed byte array = pubkey bytes + message
-        let mut signed_message = self.transmitter.as_bytes();
-        borsh::to_writer(&mut signed_message, &self.message_type)
-            .unwrap();
-
-        // Verify
-        verify_messages(
-            &self.signature.0,
-            &[&signed_message],
-            &[self.transmitter.0],
+    /// Verifies signature for the signed payload of the message in
+    /// `domain`
+    pub fn verify(&self, domain: &SigningDomain) -> bool {
+        self.transmitter.verify(
+            &self.message_type.signed_payload(domain),
+            &self.signature,
         )
     }
 
-    pub fn block(block: Block, signer: &PrivateKey) -> SignedMessage {
-        let mut message = signer.public_key().as_bytes();
-        let message_type = MessageType::Block(block);
-        borsh::to_writer(&mut message, &message_type).unwrap();
+    /// Signs a message with `signer`, which may refuse
+    pub fn new(
+        message_type: MessageType,
+        domain: &SigningDomain,
+        signer: &impl ConsensusSigner,
+    ) -> Result<SignedMessage, SignError> {
+        let signature = signer
+            .sign_request(&SignRequest::from(&message_type), domain)?;
+        Ok(SignedMessage {
+            message_type,
+            transmitter: signer.signer_public_key(),
+            signature,
+        })
+    }
 
+    fn sign(
+        message_type: MessageType,
+        domain: &SigningDomain,
+        signer: &PrivateKey,
+    ) -> SignedMessage {
+        let signature =
+            signer.sign(&message_type.signed_payload(domain));
         SignedMessage {
             message_type,
-            transmitter: PublicKey(signer.public_key()),
-            signature: Signature(signer.sign(&message)),
+            transmitter: signer.public_key(),
+            signature,
         }
     }
 
-    pub fn vote(vote: Vote, signer: &PrivateKey) -> SignedMessage {
-        let mut message = signer.public_key().as_bytes();
-        let message_type = MessageType::Vote(vote);
-        borsh::to_writer(&mut message, &message_type).unwrap();
+    pub fn block(
+        block: Block,
+        domain: &SigningDomain,
+        signer: &PrivateKey,
+    ) -> SignedMessage {
+        SignedMessage::sign(MessageType::Block(block), domain, signer)
+    }
 
-        SignedMessage {
-            message_type,
-            transmitter: PublicKey(signer.public_key()),
-            signature: Signature(signer.sign(&message)),
-        }
+    pub fn handshake(
+        genesis: BlockHash,
+        domain: &SigningDomain,
+        signer: &PrivateKey,
+    ) -> SignedMessage {
+        SignedMessage::sign(
+            MessageType::Handshake(genesis),
+            domain,
+            signer,
+        )
+    }
+
+    pub fn new_view(
+        new_view: NewView,
+        domain: &SigningDomain,
+        signer: &PrivateKey,
+    ) -> SignedMessage {
+        SignedMessage::sign(
+            MessageType::NewView(new_view),
+            domain,
+            signer,
+        )
+    }
+
+    pub fn vote(
+        vote: Vote,
+        domain: &SigningDomain,
+        signer: &PrivateKey,
+    ) -> SignedMessage {
+        SignedMessage::sign(MessageType::Vote(vote), domain, signer)
     }
 }
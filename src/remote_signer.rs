@@ -0,0 +1,227 @@
+//! Signing over a Unix socket, so that a validator's key never enters
+//! the process running consensus.
+//!
+//! The signer process (see `src/bin/pfhs-signer.rs`) serves a
+//! `GuardedSigner`, so it refuses to double sign even if the validator
+//! asks it to. Requests and responses are borsh encoded, each prefixed
+//! with its length as a little endian u32.
+//!
+//! Anyone who can connect to the socket can ask for signatures, so it
+//! must live in a directory only the validator can access.
+
+use std::{
+    io::{Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    crypto::{PublicKey, Signature, Verifier},
+    domain::SigningDomain,
+    signer::{ConsensusSigner, SignError, SignRequest},
+};
+
+/// Longest a request may take before the signer is considered
+/// unavailable
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Largest frame accepted, well above the size of any request
+const MAX_FRAME_BYTES: u32 = 4096;
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+enum Request {
+    PublicKey,
+    Sign {
+        domain: SigningDomain,
+        request: SignRequest,
+    },
+}
+
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+enum Response {
+    PublicKey(PublicKey),
+    Signature(Signature),
+    Refused(SignError),
+}
+
+/// Connection to a signer process
+pub struct RemoteSigner {
+    path: PathBuf,
+    public_key: PublicKey,
+
+    /// None after a failed request, as a late response would otherwise
+    /// be taken for the answer to the next one. We reconnect on the
+    /// next request.
+    stream: Mutex<Option<UnixStream>>,
+}
+
+impl RemoteSigner {
+    /// Connects to the signer listening at `path`
+    pub fn connect(
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<RemoteSigner> {
+        let path = path.as_ref().to_path_buf();
+        let mut stream = open(&path)?;
+        let Response::PublicKey(public_key) =
+            call(&mut stream, &Request::PublicKey)?
+        else {
+            return Err(unexpected_response());
+        };
+        Ok(RemoteSigner {
+            path,
+            public_key,
+            stream: Mutex::new(Some(stream)),
+        })
+    }
+
+    fn call(&self, request: &Request) -> std::io::Result<Response> {
+        let mut stream = self
+            .stream
+            .lock()
+            .expect("requests never panic while holding the lock");
+        let mut connection = match stream.take() {
+            Some(connection) => connection,
+            None => open(&self.path)?,
+        };
+        let response = call(&mut connection, request)?;
+        *stream = Some(connection);
+        Ok(response)
+    }
+}
+
+impl ConsensusSigner for RemoteSigner {
+    fn signer_public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    fn sign_request(
+        &self,
+        request: &SignRequest,
+        domain: &SigningDomain,
+    ) -> Result<Signature, SignError> {
+        let response = self.call(&Request::Sign {
+            domain: *domain,
+            request: request.clone(),
+        })?;
+        match response {
+            // Never hand out a signature peers would reject
+            Response::Signature(signature)
+                if self.public_key.verify(
+                    &request.signing_bytes(domain),
+                    &signature,
+                ) =>
+            {
+                Ok(signature)
+            }
+            Response::Signature(_) => Err(SignError::Unavailable(
+                "signer returned an invalid signature".to_string(),
+            )),
+            Response::Refused(err) => Err(err),
+            Response::PublicKey(_) => Err(unexpected_response().into()),
+        }
+    }
+}
+
+/// Serves requests on `listener` with `signer`, one thread per
+/// connection, until the listener fails
+pub fn serve<S: ConsensusSigner + Send + Sync + 'static>(
+    listener: UnixListener,
+    signer: Arc<S>,
+) -> std::io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let signer = signer.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = serve_connection(stream, &*signer) {
+                println!("signer: dropping connection: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve_connection(
+    mut stream: UnixStream,
+    signer: &impl ConsensusSigner,
+) -> std::io::Result<()> {
+    loop {
+        let request = match read_frame(&mut stream) {
+            Ok(request) => request,
+            // The validator hung up
+            Err(err)
+                if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                return Ok(())
+            }
+            Err(err) => return Err(err),
+        };
+        let response = match request {
+            Request::PublicKey => {
+                Response::PublicKey(signer.signer_public_key())
+            }
+            Request::Sign { domain, request } => {
+                match signer.sign_request(&request, &domain) {
+                    Ok(signature) => Response::Signature(signature),
+                    Err(err) => {
+                        println!("signer: refusing {request:?}: {err}");
+                        Response::Refused(err)
+                    }
+                }
+            }
+        };
+        write_frame(&mut stream, &response)?;
+    }
+}
+
+fn open(path: &Path) -> std::io::Result<UnixStream> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+fn call(
+    stream: &mut UnixStream,
+    request: &Request,
+) -> std::io::Result<Response> {
+    write_frame(stream, request)?;
+    read_frame(stream)
+}
+
+fn write_frame(
+    writer: &mut impl Write,
+    value: &impl BorshSerialize,
+) -> std::io::Result<()> {
+    let bytes = borsh::to_vec(value)?;
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)
+}
+
+fn read_frame<T: BorshDeserialize>(
+    reader: &mut impl Read,
+) -> std::io::Result<T> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_FRAME_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {len} bytes is too large"),
+        ));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    borsh::from_slice(&bytes)
+}
+
+fn unexpected_response() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "unexpected response from signer",
+    )
+}
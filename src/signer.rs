@@ -0,0 +1,431 @@
+//! Signing of consensus messages.
+//!
+//! Everything a validator signs is described by a `SignRequest`, so
+//! that a signer knows what it signs rather than signing opaque bytes.
+//! A `ConsensusSigner` turns requests into signatures: a `PrivateKey`
+//! simply signs, while a `GuardedSigner` first checks that the request
+//! does not conflict with anything it signed before, which is what the
+//! remote signer (see `remote_signer`) runs.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    block::{digest, BlockHash, BlockHeader},
+    certificates::producer_message,
+    crypto::{
+        proof_of_possession_message, PrivateKey, PublicKey,
+        Registration, Signature, Signer,
+    },
+    domain::{SignatureKind, SigningDomain},
+    message::{MessageType, NewViewSummary, Vote},
+};
+
+/// Something a validator signs
+#[derive(
+    Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq, Eq,
+)]
+pub enum SignRequest {
+    /// Hash of our genesis block, see `MessageType::Handshake`
+    Handshake(BlockHash),
+
+    /// Header of a block we propose. It commits to the whole block.
+    Block(BlockHeader),
+    Vote(Vote),
+    NewView(NewViewSummary),
+
+    /// Producer signature of a QC for the votes of `view`, over its
+    /// aggregated signature or, for a threshold QC, its group signature
+    Qc {
+        view: u64,
+        producer: PublicKey,
+        aggregated_signature: Signature,
+    },
+
+    /// Producer signature of an AggQC for the new views of `view`
+    AggQc {
+        view: u64,
+        producer: PublicKey,
+        aggregated_signature: Signature,
+    },
+
+    /// Proof of possession of the key, see `Registration`
+    ProofOfPossession(PublicKey),
+}
+
+impl SignRequest {
+    /// Bytes signed for this request in `domain`
+    pub fn signing_bytes(&self, domain: &SigningDomain) -> [u8; 32] {
+        match self {
+            SignRequest::Handshake(genesis) => {
+                domain.signing_bytes(SignatureKind::Handshake, genesis)
+            }
+            SignRequest::Block(header) => {
+                domain.signing_bytes(SignatureKind::Block, header)
+            }
+            SignRequest::Vote(vote) => {
+                domain.signing_bytes(SignatureKind::Vote, vote)
+            }
+            SignRequest::NewView(summary) => {
+                summary.signed_payload(domain)
+            }
+            SignRequest::Qc {
+                view,
+                producer,
+                aggregated_signature,
+            } => producer_message(
+                domain,
+                SignatureKind::QcProducer,
+                *view,
+                producer,
+                aggregated_signature,
+            ),
+            SignRequest::AggQc {
+                view,
+                producer,
+                aggregated_signature,
+            } => producer_message(
+                domain,
+                SignatureKind::AggQcProducer,
+                *view,
+                producer,
+                aggregated_signature,
+            ),
+            SignRequest::ProofOfPossession(public_key) => {
+                proof_of_possession_message(public_key)
+            }
+        }
+    }
+
+    /// Kind and view of the request if signing it twice with different
+    /// contents for the same view would be a double sign. Handshakes
+    /// and proofs of possession never conflict.
+    pub fn view(&self) -> Option<(SignatureKind, u64)> {
+        match self {
+            SignRequest::Block(header) => {
+                Some((SignatureKind::Block, header.view))
+            }
+            SignRequest::Vote(vote) => {
+                Some((SignatureKind::Vote, vote.view))
+            }
+            SignRequest::NewView(summary) => {
+                Some((SignatureKind::NewView, summary.view))
+            }
+            SignRequest::Qc { view, .. } => {
+                Some((SignatureKind::QcProducer, *view))
+            }
+            SignRequest::AggQc { view, .. } => {
+                Some((SignatureKind::AggQcProducer, *view))
+            }
+            SignRequest::Handshake(_)
+            | SignRequest::ProofOfPossession(_) => None,
+        }
+    }
+
+    /// Key the request is signed on behalf of, if it names one
+    fn signer(&self) -> Option<&PublicKey> {
+        match self {
+            SignRequest::Qc { producer, .. }
+            | SignRequest::AggQc { producer, .. }
+            | SignRequest::ProofOfPossession(producer) => {
+                Some(producer)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<&MessageType> for SignRequest {
+    fn from(message_type: &MessageType) -> Self {
+        match message_type {
+            MessageType::Vote(vote)
+            | MessageType::ThresholdVote { vote, .. } => {
+                SignRequest::Vote(vote.clone())
+            }
+            MessageType::NewView(eta) => {
+                SignRequest::NewView(eta.summary())
+            }
+            MessageType::Block(block) => {
+                SignRequest::Block(block.header())
+            }
+            MessageType::Handshake(genesis) => {
+                SignRequest::Handshake(*genesis)
+            }
+        }
+    }
+}
+
+/// Reason a signer did not sign
+#[derive(
+    Debug, Clone, BorshSerialize, BorshDeserialize, PartialEq, Eq,
+)]
+pub enum SignError {
+    /// Something else of the same kind was already signed for the view
+    Equivocation { kind: SignatureKind, view: u64 },
+
+    /// Something of the same kind was already signed for a later view
+    StaleView {
+        kind: SignatureKind,
+        view: u64,
+        latest: u64,
+    },
+
+    /// The request is for another chain or protocol version than the
+    /// signer's
+    WrongDomain,
+
+    /// The request is on behalf of another key than the signer's
+    WrongSigner,
+
+    /// The signer could not be reached, or could not record what it
+    /// signed
+    Unavailable(String),
+}
+
+impl std::fmt::Display for SignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignError::Equivocation { kind, view } => write!(
+                f,
+                "already signed another {kind:?} for view {view}"
+            ),
+            SignError::StaleView { kind, view, latest } => write!(
+                f,
+                "refusing {kind:?} for view {view}, already signed for \
+                 view {latest}"
+            ),
+            SignError::WrongDomain => {
+                f.write_str("request is for another signing domain")
+            }
+            SignError::WrongSigner => {
+                f.write_str("request is for another key")
+            }
+            SignError::Unavailable(reason) => {
+                write!(f, "signer unavailable: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SignError {}
+
+impl From<std::io::Error> for SignError {
+    fn from(err: std::io::Error) -> Self {
+        SignError::Unavailable(err.to_string())
+    }
+}
+
+/// Signs requests with a validator's key
+pub trait ConsensusSigner {
+    /// Public key of the key signing requests
+    fn signer_public_key(&self) -> PublicKey;
+
+    fn sign_request(
+        &self,
+        request: &SignRequest,
+        domain: &SigningDomain,
+    ) -> Result<Signature, SignError>;
+
+    /// Our key with a proof of possession
+    fn registration(&self) -> Result<Registration, SignError> {
+        let public_key = self.signer_public_key();
+        Ok(Registration {
+            public_key,
+            proof_of_possession: self.sign_request(
+                &SignRequest::ProofOfPossession(public_key),
+                // Proofs of possession do not depend on the domain
+                &SigningDomain::new(0),
+            )?,
+        })
+    }
+}
+
+/// Signs anything it is asked to, without double-sign protection
+impl ConsensusSigner for PrivateKey {
+    fn signer_public_key(&self) -> PublicKey {
+        self.public_key()
+    }
+
+    fn sign_request(
+        &self,
+        request: &SignRequest,
+        domain: &SigningDomain,
+    ) -> Result<Signature, SignError> {
+        Ok(self.sign(&request.signing_bytes(domain)))
+    }
+}
+
+/// Signs with a key held in memory, refusing to sign anything that
+/// conflicts with what it signed before: for each kind of request,
+/// nothing for a view below the latest one signed, and nothing but the
+/// very same request for that view. Re-signing the same request is
+/// allowed, so a validator that lost a signature can ask again.
+///
+/// The latest signed views can be persisted to a file, so that the
+/// protection survives restarts.
+pub struct GuardedSigner {
+    private_key: PrivateKey,
+    domain: SigningDomain,
+
+    /// Where the watermarks are persisted, if anywhere
+    state_path: Option<PathBuf>,
+    watermarks: Mutex<Watermarks>,
+}
+
+/// Latest signed view and digest of what was signed for it, for each
+/// kind of request
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize)]
+struct Watermarks {
+    block: Option<Watermark>,
+    vote: Option<Watermark>,
+    new_view: Option<Watermark>,
+    qc: Option<Watermark>,
+    aggqc: Option<Watermark>,
+}
+
+#[derive(Clone, Copy, Debug, BorshSerialize, BorshDeserialize)]
+struct Watermark {
+    view: u64,
+    digest: [u8; 32],
+}
+
+impl Watermarks {
+    fn get_mut(
+        &mut self,
+        kind: SignatureKind,
+    ) -> Option<&mut Option<Watermark>> {
+        match kind {
+            SignatureKind::Block => Some(&mut self.block),
+            SignatureKind::Vote => Some(&mut self.vote),
+            SignatureKind::NewView => Some(&mut self.new_view),
+            SignatureKind::QcProducer => Some(&mut self.qc),
+            SignatureKind::AggQcProducer => Some(&mut self.aggqc),
+            SignatureKind::Handshake | SignatureKind::Transaction => {
+                None
+            }
+        }
+    }
+}
+
+impl GuardedSigner {
+    /// Signer for `domain` that does not persist what it signed
+    pub fn new(
+        private_key: PrivateKey,
+        domain: SigningDomain,
+    ) -> GuardedSigner {
+        GuardedSigner {
+            private_key,
+            domain,
+            state_path: None,
+            watermarks: Mutex::new(Watermarks::default()),
+        }
+    }
+
+    /// Signer for `domain` persisting what it signed at `path`, picking
+    /// up where it left off if the file exists
+    pub fn with_state_file(
+        private_key: PrivateKey,
+        domain: SigningDomain,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<GuardedSigner> {
+        let path = path.as_ref().to_path_buf();
+        let watermarks = match fs::read(&path) {
+            Ok(bytes) => borsh::from_slice(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Watermarks::default()
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(GuardedSigner {
+            private_key,
+            domain,
+            state_path: Some(path),
+            watermarks: Mutex::new(watermarks),
+        })
+    }
+
+    /// Writes the watermarks to a temporary file and renames it over
+    /// the state file, so the state file is never left half written
+    fn persist(&self, watermarks: &Watermarks) -> std::io::Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+        let temporary = path.with_extension("tmp");
+        let file = fs::File::create(&temporary)?;
+        borsh::to_writer(&file, watermarks)?;
+        file.sync_all()?;
+        fs::rename(temporary, path)
+    }
+}
+
+impl ConsensusSigner for GuardedSigner {
+    fn signer_public_key(&self) -> PublicKey {
+        self.private_key.public_key()
+    }
+
+    fn sign_request(
+        &self,
+        request: &SignRequest,
+        domain: &SigningDomain,
+    ) -> Result<Signature, SignError> {
+        if request
+            .signer()
+            .is_some_and(|signer| *signer != self.signer_public_key())
+        {
+            return Err(SignError::WrongSigner);
+        }
+        let is_proof_of_possession =
+            matches!(request, SignRequest::ProofOfPossession(_));
+        if *domain != self.domain && !is_proof_of_possession {
+            return Err(SignError::WrongDomain);
+        }
+
+        let message = request.signing_bytes(domain);
+        let Some((kind, view)) = request.view() else {
+            return Ok(self.private_key.sign(&message));
+        };
+
+        // Hold the lock until the signature is recorded, so concurrent
+        // requests cannot both pass the check
+        let mut watermarks = self
+            .watermarks
+            .lock()
+            .expect("signing never panics while holding the lock");
+        let message_digest = digest(&message);
+        let mut updated = watermarks.clone();
+        let watermark = updated
+            .get_mut(kind)
+            .expect("requests with a view have a watermark");
+        match watermark {
+            Some(latest) if view < latest.view => {
+                return Err(SignError::StaleView {
+                    kind,
+                    view,
+                    latest: latest.view,
+                });
+            }
+            Some(latest)
+                if view == latest.view
+                    && message_digest != latest.digest =>
+            {
+                return Err(SignError::Equivocation { kind, view });
+            }
+            Some(latest) if view == latest.view => {}
+            _ => {
+                *watermark = Some(Watermark {
+                    view,
+                    digest: message_digest,
+                });
+                // Never sign what we could not record
+                self.persist(&updated)?;
+                *watermarks = updated;
+            }
+        }
+        Ok(self.private_key.sign(&message))
+    }
+}
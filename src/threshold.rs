@@ -0,0 +1,425 @@
+//! Threshold-signed quorum certificates.
+//!
+//! In threshold mode every validator holds a share of a single group
+//! key, dealt once for a validator set. A quorum of partial signatures
+//! over a vote combines into one signature by the group key, so a
+//! certificate is the vote plus that signature and its producer's,
+//! whatever the size of the validator set. Verifying the group
+//! signature is a single signature check against the group public key. This suits light clients, which only need to
+//! know the group public key of an epoch.
+//!
+//! Consensus runs in threshold mode for the epochs whose group key is
+//! known, i.e. the genesis epoch if `GenesisConfig::threshold_keys` is
+//! set. Validators then send a partial signature along with every vote,
+//! and the primary certifies a quorum of them with a `ThresholdQC`. It
+//! falls back to an aggregate QC if the partials do not combine, and
+//! epochs without a group key use aggregate QCs only.
+//!
+//! The setup here is dealer based: whoever deals learns the group key,
+//! so it is only meant for tests and trusted setups.
+
+use std::collections::{BTreeMap, HashMap};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use rand::{CryptoRng, RngCore};
+
+use crate::{
+    certificates::{
+        verify_leader, verify_producer_signature, CertificateError,
+        VerifyContext,
+    },
+    crypto::{
+        PrivateKey, PublicKey, Scheme, Signature, Signer, Threshold,
+        Verifier,
+    },
+    domain::{SignatureKind, SigningDomain},
+    message::Vote,
+    signer::{ConsensusSigner, SignError, SignRequest},
+    validator_set::ValidatorSet,
+};
+
+/// Public side of a dealt group key
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize)]
+pub struct ThresholdKeys {
+    /// Key threshold QCs are verified against
+    pub group_public_key: PublicKey,
+
+    /// Public key of every share, in the canonical order of the
+    /// validator set the keys were dealt for
+    pub share_public_keys: Vec<PublicKey>,
+
+    /// Number of partial signatures needed to form a QC
+    pub threshold: usize,
+}
+
+/// Reason threshold keys cannot serve an epoch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThresholdKeysError {
+    /// Fewer partials than a quorum of the validator set would form a
+    /// QC, so a QC would not prove a quorum voted
+    ThresholdBelowQuorum { threshold: usize, quorum: usize },
+
+    /// Not exactly one share per validator of the set
+    ShareCountMismatch { shares: usize, validators: usize },
+}
+
+impl std::fmt::Display for ThresholdKeysError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThresholdKeysError::ThresholdBelowQuorum {
+                threshold,
+                quorum,
+            } => {
+                write!(f, "threshold {threshold} below quorum {quorum}")
+            }
+            ThresholdKeysError::ShareCountMismatch {
+                shares,
+                validators,
+            } => {
+                write!(f, "{shares} shares for {validators} validators")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ThresholdKeysError {}
+
+/// Share of the group key held by the validator at `index` in the
+/// validator set
+#[derive(Debug, Clone)]
+pub struct KeyShare {
+    pub index: usize,
+    pub private_key: PrivateKey,
+}
+
+/// Signature by one share over a vote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialSignature {
+    pub index: usize,
+    pub signature: Signature,
+}
+
+/// Deals a group key for `validator_set`, with one share per validator
+/// in canonical order and the quorum threshold of the set as threshold
+pub fn deal<R: RngCore + CryptoRng>(
+    validator_set: &ValidatorSet,
+    rng: &mut R,
+) -> (ThresholdKeys, Vec<KeyShare>) {
+    let threshold = validator_set.quorum_threshold();
+    let (group_public_key, private_keys) =
+        Scheme::deal(threshold, validator_set.len(), rng);
+    let keys = ThresholdKeys {
+        group_public_key,
+        share_public_keys: private_keys
+            .iter()
+            .map(Signer::public_key)
+            .collect(),
+        threshold,
+    };
+    let shares = private_keys
+        .into_iter()
+        .enumerate()
+        .map(|(index, private_key)| KeyShare { index, private_key })
+        .collect();
+    (keys, shares)
+}
+
+impl KeyShare {
+    /// Partial signature over `vote`. It is over the same bytes as an
+    /// ordinary vote signature, so the combined signature verifies like
+    /// a vote signed by the group key.
+    pub fn sign_vote(
+        &self,
+        vote: &Vote,
+        domain: &SigningDomain,
+    ) -> PartialSignature {
+        PartialSignature {
+            index: self.index,
+            signature: self
+                .private_key
+                .sign(&domain.signing_bytes(SignatureKind::Vote, vote)),
+        }
+    }
+}
+
+impl ThresholdKeys {
+    /// Whether `share` is one of the shares these keys were dealt with
+    pub fn holds(&self, share: &KeyShare) -> bool {
+        self.share_public_keys.get(share.index)
+            == Some(&share.private_key.public_key())
+    }
+
+    /// Whether `partial` is a signature over `vote` by the share it
+    /// claims to be from
+    pub fn verify_partial(
+        &self,
+        vote: &Vote,
+        partial: &PartialSignature,
+        domain: &SigningDomain,
+    ) -> bool {
+        self.share_public_keys
+            .get(partial.index)
+            .is_some_and(|public_key| {
+                public_key.verify(
+                    &domain.signing_bytes(SignatureKind::Vote, vote),
+                    &partial.signature,
+                )
+            })
+    }
+}
+
+/// QC signed by the group key. Its size does not depend on the number
+/// of validators.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, BorshSerialize, BorshDeserialize,
+)]
+pub struct ThresholdQC {
+    pub vote: Vote,
+
+    /// Signature by the group key over the vote
+    pub group_signature: Signature,
+
+    /// Signature by the producer over the view and the group signature
+    pub signature: Signature,
+    pub producer: PublicKey,
+}
+
+impl ThresholdQC {
+    /// Combines partial signatures over `vote` into a signature by the
+    /// group key. Partials from the same share count once, and a
+    /// partial from a share that does not exist is an error. The
+    /// combined signature is checked, and if it is invalid the partials
+    /// are checked one by one and the invalid ones left out, so a few
+    /// bad partials cannot spoil a QC.
+    pub fn combine_partials(
+        vote: &Vote,
+        partials: &[PartialSignature],
+        keys: &ThresholdKeys,
+        domain: &SigningDomain,
+    ) -> Result<Signature, CertificateError> {
+        let mut shares = BTreeMap::<usize, Signature>::new();
+        for partial in partials {
+            if partial.index >= keys.share_public_keys.len() {
+                return Err(CertificateError::UnknownSigner {
+                    index: partial.index as u64,
+                });
+            }
+            shares.insert(partial.index, partial.signature);
+        }
+
+        let group_signature = ThresholdQC::combine(&shares, keys)?;
+        if verify_group_signature(
+            vote,
+            &group_signature,
+            &keys.group_public_key,
+            domain,
+        )
+        .is_ok()
+        {
+            return Ok(group_signature);
+        }
+
+        // Some partial is invalid, keep the valid ones only
+        shares.retain(|&index, &mut signature| {
+            keys.verify_partial(
+                vote,
+                &PartialSignature { index, signature },
+                domain,
+            )
+        });
+        ThresholdQC::combine(&shares, keys)
+    }
+
+    /// Combines the signatures of the first `keys.threshold` shares.
+    /// The result is valid if those signatures are.
+    fn combine(
+        shares: &BTreeMap<usize, Signature>,
+        keys: &ThresholdKeys,
+    ) -> Result<Signature, CertificateError> {
+        if shares.len() < keys.threshold {
+            return Err(CertificateError::InsufficientQuorum {
+                signers: shares.len(),
+                threshold: keys.threshold,
+            });
+        }
+
+        // Any threshold of them give the same signature, so use no
+        // more than needed
+        let partials: Vec<(usize, Signature)> = shares
+            .iter()
+            .map(|(&index, &signature)| (index, signature))
+            .take(keys.threshold)
+            .collect();
+        Ok(Scheme::combine_shares(&partials)
+            .expect("partials are non empty and distinct"))
+    }
+
+    /// QC for `vote` with a group signature over it, e.g. from
+    /// `combine_partials`, produced by `signer`
+    pub fn from_group_signature(
+        vote: Vote,
+        group_signature: Signature,
+        domain: &SigningDomain,
+        signer: &impl ConsensusSigner,
+    ) -> Result<ThresholdQC, SignError> {
+        let producer = signer.signer_public_key();
+        let signature = signer.sign_request(
+            &SignRequest::Qc {
+                view: vote.view,
+                producer,
+                aggregated_signature: group_signature,
+            },
+            domain,
+        )?;
+        Ok(ThresholdQC {
+            vote,
+            group_signature,
+            signature,
+            producer,
+        })
+    }
+
+    /// A threshold QC is valid if the epoch of the certified view has
+    /// a group key and
+    /// 1) producer is the leader of the view after the certified view,
+    ///    i.e. the primary the votes were sent to
+    /// 2) producer signature is valid
+    /// 3) group signature verifies against the group key
+    pub fn verify(
+        &self,
+        ctx: &VerifyContext,
+    ) -> Result<(), CertificateError> {
+        let Some(keys) = ctx
+            .epochs
+            .threshold_keys(self.vote.view)
+        else {
+            return Err(CertificateError::NoGroupKey {
+                view: self.vote.view,
+            });
+        };
+
+        let expected_producer = {
+            #[inline(always)]
+            || {
+                verify_leader(
+                    &self.producer,
+                    self.vote.view + 1,
+                    ctx.epochs,
+                )
+            }
+        };
+
+        let valid_producer_signature = {
+            #[inline(always)]
+            || {
+                verify_producer_signature(
+                    &ctx.domain,
+                    SignatureKind::QcProducer,
+                    self.vote.view,
+                    &self.producer,
+                    &self.signature,
+                    &self.group_signature,
+                )
+            }
+        };
+
+        let valid_group_signature = {
+            #[inline(always)]
+            || self.verify_with_key(&keys.group_public_key, &ctx.domain)
+        };
+
+        // This is sorted by compute cost and will short circuit on the
+        // first error
+        ctx.verify_once(self, || {
+            expected_producer()?;
+            valid_producer_signature()?;
+            valid_group_signature()
+        })
+    }
+
+    /// Checks the group signature against the group public key of the
+    /// epoch of the certified view, e.g. for a light client that only
+    /// knows that key and does not care who produced the QC
+    pub fn verify_with_key(
+        &self,
+        group_public_key: &PublicKey,
+        domain: &SigningDomain,
+    ) -> Result<(), CertificateError> {
+        verify_group_signature(
+            &self.vote,
+            &self.group_signature,
+            group_public_key,
+            domain,
+        )
+    }
+}
+
+fn verify_group_signature(
+    vote: &Vote,
+    group_signature: &Signature,
+    group_public_key: &PublicKey,
+    domain: &SigningDomain,
+) -> Result<(), CertificateError> {
+    let message = domain.signing_bytes(SignatureKind::Vote, vote);
+    group_public_key
+        .verify(&message, group_signature)
+        .then_some(())
+        .ok_or(CertificateError::BadAggregateSignature)
+}
+
+/// Partial signatures a primary receives along with the votes for the
+/// previous view, grouped by vote
+#[derive(Debug)]
+pub struct PartialAggregator {
+    /// Group key of the epoch of the voted view
+    keys: ThresholdKeys,
+
+    /// Domain the partials are signed in
+    domain: SigningDomain,
+    partials: HashMap<Vote, Vec<PartialSignature>>,
+}
+
+impl PartialAggregator {
+    pub fn new(
+        keys: ThresholdKeys,
+        domain: SigningDomain,
+    ) -> PartialAggregator {
+        PartialAggregator {
+            keys,
+            domain,
+            partials: HashMap::new(),
+        }
+    }
+
+    /// Keeps a partial signature over `vote` by the validator at
+    /// `index`. It is only checked when the QC is built.
+    pub fn insert(&mut self, vote: Vote, partial: PartialSignature) {
+        self.partials
+            .entry(vote)
+            .or_default()
+            .push(partial);
+    }
+
+    /// Threshold QC for `vote`, produced by `signer`. Returns None if
+    /// there are not enough valid partials for it.
+    pub fn certificate(
+        &self,
+        vote: &Vote,
+        signer: &impl ConsensusSigner,
+    ) -> Option<Result<ThresholdQC, SignError>> {
+        let partials = self.partials.get(vote)?;
+        let group_signature = ThresholdQC::combine_partials(
+            vote,
+            partials,
+            &self.keys,
+            &self.domain,
+        )
+        .ok()?;
+        Some(ThresholdQC::from_group_signature(
+            vote.clone(),
+            group_signature,
+            &self.domain,
+            signer,
+        ))
+    }
+}
@@ -1,49 +1,124 @@
-use bls_signatures::{
-    verify_messages, PrivateKey, PublicKey, Serialize, Signature,
-};
 use borsh::BorshSerialize;
-use rand::{random, thread_rng};
+use rand::{thread_rng, CryptoRng, Rng};
 
-#[derive(Clone, Debug)]
-pub struct Transaction {
-    message: Vec<u8>,
-    signature: Signature,
-    pubkey: PublicKey,
+use crate::{
+    crypto::{
+        self, PrivateKey, PublicKey, Signature, Signer, Verifier,
+    },
+    domain::{SignatureKind, SigningDomain},
+};
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, BorshSerialize)]
+pub enum Transaction {
+    /// Opaque payload signed by a user
+    User(UserTransaction),
+
+    /// Special transaction that defines the validator set of the next
+    /// epoch once the block containing it commits
+    Reconfiguration(Reconfiguration),
 }
 
-impl BorshSerialize for Transaction {
-    fn serialize<W: std::io::prelude::Write>(
-        &self,
-        writer: &mut W,
-    ) -> std::io::Result<()> {
-        writer.write_all(&self.message)?;
-        writer.write_all(&self.signature.as_bytes())?;
-        writer.write_all(&self.pubkey.as_bytes())
+impl Transaction {
+    /// Verifies the transaction is well formed and signed for `domain`
+    pub fn verify(&self, domain: &SigningDomain) -> bool {
+        match self {
+            Transaction::User(tx) => tx.verify(domain),
+            Transaction::Reconfiguration(reconfiguration) => {
+                !reconfiguration.validators.is_empty()
+                    && reconfiguration
+                        .validators
+                        .iter()
+                        .all(crypto::Registration::verify)
+            }
+        }
+    }
+
+    /// Produces a new random transaction that should have
+    /// self.verify(domain) == true
+    pub fn new_valid(domain: &SigningDomain) -> Transaction {
+        Transaction::new_valid_with_rng(domain, &mut thread_rng())
+    }
+
+    /// Same as `new_valid`, drawing randomness from `rng`, e.g. seeded
+    /// to reproduce a run
+    pub fn new_valid_with_rng<R: Rng + CryptoRng>(
+        domain: &SigningDomain,
+        rng: &mut R,
+    ) -> Transaction {
+        Transaction::User(UserTransaction::new_valid_with_rng(
+            domain, rng,
+        ))
+    }
+
+    /// Produces a new random transaction that should have self.verify()
+    /// == false
+    pub fn new_invalid() -> Transaction {
+        Transaction::new_invalid_with_rng(&mut thread_rng())
+    }
+
+    /// Same as `new_invalid`, drawing randomness from `rng`
+    pub fn new_invalid_with_rng<R: Rng + CryptoRng>(
+        rng: &mut R,
+    ) -> Transaction {
+        Transaction::User(UserTransaction::new_invalid_with_rng(rng))
+    }
+
+    /// Produces a reconfiguration to the given validators
+    pub fn reconfiguration(
+        validators: impl IntoIterator<Item = crypto::Registration>,
+    ) -> Transaction {
+        Transaction::Reconfiguration(Reconfiguration {
+            validators: validators.into_iter().collect(),
+        })
     }
 }
 
-impl Transaction {
-    /// Verifies the internal signature
-    pub fn verify(&self) -> bool {
-        verify_messages(
+#[derive(Clone, Debug, BorshSerialize)]
+pub struct Reconfiguration {
+    /// Full membership of the next epoch, with proofs of possession.
+    /// Canonical ordering is recomputed by `ValidatorSet::new`, so order
+    /// here is irrelevant.
+    pub validators: Vec<crypto::Registration>,
+}
+
+#[derive(Clone, Debug, BorshSerialize)]
+pub struct UserTransaction {
+    message: Vec<u8>,
+    signature: Signature,
+    pubkey: PublicKey,
+}
+
+impl UserTransaction {
+    /// Verifies the internal signature was produced for `domain`
+    pub fn verify(&self, domain: &SigningDomain) -> bool {
+        self.pubkey.verify(
+            &UserTransaction::signed_payload(domain, &self.message),
             &self.signature,
-            &[&self.message],
-            &[self.pubkey],
         )
     }
 
-    /// Produces a new random transaction that should have self.verify()
-    /// == true
-    pub fn new_valid() -> Transaction {
+    /// Produces a new random transaction that should have
+    /// self.verify(domain) == true
+    pub fn new_valid(domain: &SigningDomain) -> UserTransaction {
+        UserTransaction::new_valid_with_rng(domain, &mut thread_rng())
+    }
+
+    /// Same as `new_valid`, drawing randomness from `rng`
+    pub fn new_valid_with_rng<R: Rng + CryptoRng>(
+        domain: &SigningDomain,
+        rng: &mut R,
+    ) -> UserTransaction {
         // Generate new user, message
-        let user = PrivateKey::generate(&mut thread_rng());
-        let message = (0..128).map(|_| random()).collect();
+        let user = PrivateKey::generate(rng);
+        let message: Vec<u8> = (0..128).map(|_| rng.gen()).collect();
 
         // Produce valid signature
-        let signature = user.sign(&message);
+        let signature = user
+            .sign(&UserTransaction::signed_payload(domain, &message));
 
         // Bundle into transaction
-        Transaction {
+        UserTransaction {
             message,
             signature,
             pubkey: user.public_key(),
@@ -52,20 +127,34 @@ impl Transaction {
 
     /// Produces a new random transaction that should have self.verify()
     /// == false
-    pub fn new_invalid() -> Transaction {
+    pub fn new_invalid() -> UserTransaction {
+        UserTransaction::new_invalid_with_rng(&mut thread_rng())
+    }
+
+    /// Same as `new_invalid`, drawing randomness from `rng`
+    pub fn new_invalid_with_rng<R: Rng + CryptoRng>(
+        rng: &mut R,
+    ) -> UserTransaction {
         // Generate new user, message
-        let user = PrivateKey::generate(&mut thread_rng());
-        let message = (0..128).map(|_| random()).collect();
+        let user = PrivateKey::generate(rng);
+        let message: Vec<u8> = (0..128).map(|_| rng.gen()).collect();
 
-        // Produce invalid signature
-        // (vanishingly 1 in 256^96 ≈ 10^231 chance of being valid)
-        let signature = Signature::from_bytes(&[0; 96]).unwrap();
+        // Produce invalid signature, over the message in no domain
+        let signature = user.sign(&message);
 
         // Bundle into transaction
-        Transaction {
+        UserTransaction {
             message,
             signature,
             pubkey: user.public_key(),
         }
     }
+
+    /// Bytes signed by the user for `message`
+    fn signed_payload(
+        domain: &SigningDomain,
+        message: &[u8],
+    ) -> [u8; 32] {
+        domain.signing_bytes(SignatureKind::Transaction, message)
+    }
 }
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use borsh::BorshSerialize;
+
+use crate::crypto::{PublicKey, Registration};
+
+/// Canonical ordering of the validators participating in consensus.
+///
+/// Keys are sorted (bytewise by their compressed encoding for BLS) so
+/// that every node derives the same index for the same validator
+/// regardless of the order in which it learned about its peers. The
+/// index is used by the leader schedule and is the single source of
+/// truth for membership checks during certificate validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatorSet {
+    /// Validators sorted by public key
+    validators: Vec<PublicKey>,
+
+    /// Reverse lookup from public key to canonical index
+    indices: HashMap<PublicKey, u64>,
+}
+
+impl ValidatorSet {
+    /// Builds the canonical validator set. Duplicate keys are removed.
+    ///
+    /// The keys are trusted to have been registered with a valid proof
+    /// of possession, see `from_registrations`.
+    pub fn new(
+        validators: impl IntoIterator<Item = PublicKey>,
+    ) -> Self {
+        let mut validators: Vec<PublicKey> =
+            validators.into_iter().collect();
+        validators.sort_unstable();
+        validators.dedup();
+
+        let indices = validators
+            .iter()
+            .enumerate()
+            .map(|(i, pk)| (*pk, i as u64))
+            .collect();
+
+        ValidatorSet {
+            validators,
+            indices,
+        }
+    }
+
+    /// Builds the canonical validator set from registrations, checking
+    /// every proof of possession. Returns None if any proof is invalid
+    /// or there are no registrations, as no leader could be scheduled.
+    pub fn from_registrations(
+        registrations: impl IntoIterator<Item = Registration>,
+    ) -> Option<Self> {
+        let validators: Vec<PublicKey> = registrations
+            .into_iter()
+            .map(|registration| {
+                registration
+                    .verify()
+                    .then_some(registration.public_key)
+            })
+            .collect::<Option<_>>()?;
+        if validators.is_empty() {
+            return None;
+        }
+        Some(ValidatorSet::new(validators))
+    }
+
+    /// Number of validators in the set
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    /// Whether the given key belongs to a validator in this set
+    pub fn contains(&self, public_key: &PublicKey) -> bool {
+        self.indices.contains_key(public_key)
+    }
+
+    /// Canonical index of a validator, if it is in the set
+    pub fn index_of(&self, public_key: &PublicKey) -> Option<u64> {
+        self.indices.get(public_key).copied()
+    }
+
+    /// Validator at a canonical index, if the index is in range
+    pub fn get(&self, index: u64) -> Option<&PublicKey> {
+        self.validators.get(index as usize)
+    }
+
+    /// Deterministic round-robin leader for a view
+    pub fn leader(&self, view: u64) -> &PublicKey {
+        &self.validators[(view % self.len() as u64) as usize]
+    }
+
+    /// Number of byzantine validators tolerated, f = floor((n-1)/3)
+    pub fn fault_tolerance(&self) -> usize {
+        self.len().saturating_sub(1) / 3
+    }
+
+    /// Smallest number of validators forming a quorum,
+    /// q = floor((n+f)/2) + 1.
+    ///
+    /// Any two quorums intersect in more than f validators (2q - n > f),
+    /// so they share at least one honest validator (safety), and the
+    /// n - f honest validators alone always form a quorum (liveness).
+    /// For n = 3f+1 this is the familiar 2f+1, but it also holds when n
+    /// is not of that form, e.g. n = 6 gives f = 1 and q = 4.
+    pub fn quorum_threshold(&self) -> usize {
+        (self.len() + self.fault_tolerance()) / 2 + 1
+    }
+
+    /// Whether `num` distinct validators form a quorum
+    pub fn is_supermajority(&self, num: usize) -> bool {
+        num >= self.quorum_threshold()
+    }
+
+    /// Iterate over validators in canonical order
+    pub fn iter(&self) -> impl Iterator<Item = &PublicKey> {
+        self.validators.iter()
+    }
+}
+
+impl BorshSerialize for ValidatorSet {
+    fn serialize<W: std::io::prelude::Write>(
+        &self,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        // Canonical order, so equal sets always serialize identically
+        self.validators.serialize(writer)
+    }
+}
+
+/// Set of validators as a bitmap over their canonical index in a
+/// `ValidatorSet`. Bit `i` (least significant bit first within each
+/// byte) is set if the validator at index `i` is a member. A bitmap
+/// for a set of `n` validators is always `n.div_ceil(8)` bytes long,
+/// with the padding bits of the last byte unset.
+#[derive(
+    Clone, Debug, Default, BorshSerialize, Hash, PartialEq, Eq,
+)]
+pub struct SignerBitmap {
+    bits: Vec<u8>,
+}
+
+impl SignerBitmap {
+    /// Empty bitmap sized for a set of `validators` validators
+    pub fn new(validators: usize) -> SignerBitmap {
+        SignerBitmap {
+            bits: vec![0; validators.div_ceil(8)],
+        }
+    }
+
+    /// Bitmap over `validator_set` with the given members. Returns None
+    /// if any of them is not in the set.
+    pub fn from_signers<'a>(
+        validator_set: &ValidatorSet,
+        signers: impl IntoIterator<Item = &'a PublicKey>,
+    ) -> Option<SignerBitmap> {
+        let mut bitmap = SignerBitmap::new(validator_set.len());
+        for signer in signers {
+            bitmap.insert(validator_set.index_of(signer)?);
+        }
+        Some(bitmap)
+    }
+
+    /// Adds the validator at `index`. Returns false if it was already
+    /// a member.
+    ///
+    /// Panics if `index` is past the end of the bitmap.
+    pub fn insert(&mut self, index: u64) -> bool {
+        let (byte, mask) = Self::position(index);
+        let was_member = self.bits[byte] & mask != 0;
+        self.bits[byte] |= mask;
+        !was_member
+    }
+
+    /// Whether the validator at `index` is a member
+    pub fn contains(&self, index: u64) -> bool {
+        let (byte, mask) = Self::position(index);
+        self.bits
+            .get(byte)
+            .is_some_and(|bits| bits & mask != 0)
+    }
+
+    /// Number of members
+    pub fn count(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    /// Indices of the members in increasing (canonical) order
+    pub fn indices(&self) -> impl Iterator<Item = u64> + '_ {
+        self.bits
+            .iter()
+            .enumerate()
+            .flat_map(|(byte, bits)| {
+                (0..8)
+                    .filter(move |bit| bits & (1 << bit) != 0)
+                    .map(move |bit| (byte * 8 + bit) as u64)
+            })
+    }
+
+    /// Decodes a bitmap. It still has to be checked against the
+    /// validator set it refers to before its members can be trusted.
+    pub fn from_bytes(bits: Vec<u8>) -> SignerBitmap {
+        SignerBitmap { bits }
+    }
+
+    /// Encoded bitmap
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    fn position(index: u64) -> (usize, u8) {
+        ((index / 8) as usize, 1 << (index % 8))
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{
+    certificates::QuorumCertificate,
+    crypto::{Accumulator, Aggregator, PublicKey, Scheme, Signature},
+    domain::SigningDomain,
+    message::Vote,
+    signer::{ConsensusSigner, SignError},
+    validator_set::{SignerBitmap, ValidatorSet},
+};
+
+/// Votes for one (view, blockhash) folded into a running aggregate
+#[derive(Debug, Clone)]
+pub struct VoteAggregate {
+    /// Sum of the signatures folded in so far. It is only turned into
+    /// a signature when the QC is built.
+    pub accumulator: Accumulator,
+    pub signers: SignerBitmap,
+
+    /// Number of signers, kept next to the bitmap so that checking for
+    /// a quorum is O(1)
+    pub count: usize,
+}
+
+/// Aggregates the (sigverified) votes a primary receives for the
+/// previous view as they arrive. Each signature is folded into the
+/// running aggregate of the vote it is for, so neither checking for a
+/// quorum nor building the QC depends on how many votes were received.
+#[derive(Debug)]
+pub struct VoteAggregator {
+    /// Validator set of the epoch of the voted view
+    validator_set: ValidatorSet,
+
+    /// Domain the QCs are produced in
+    domain: SigningDomain,
+    aggregates: HashMap<Vote, VoteAggregate>,
+}
+
+impl VoteAggregator {
+    pub fn new(
+        validator_set: ValidatorSet,
+        domain: SigningDomain,
+    ) -> VoteAggregator {
+        VoteAggregator {
+            validator_set,
+            domain,
+            aggregates: HashMap::new(),
+        }
+    }
+
+    /// Folds a vote signature into the aggregate for its vote. Returns
+    /// whether that aggregate has reached a quorum. Votes from
+    /// non-validators and repeated votes from the same signer are
+    /// ignored.
+    pub fn insert(
+        &mut self,
+        vote: Vote,
+        signer: &PublicKey,
+        signature: &Signature,
+    ) -> bool {
+        let Some(index) = self.validator_set.index_of(signer) else {
+            return false;
+        };
+        let validators = self.validator_set.len();
+        let aggregate = match self.aggregates.entry(vote) {
+            Entry::Occupied(entry) => {
+                let aggregate = entry.into_mut();
+                if aggregate.signers.insert(index) {
+                    Scheme::add_assign(
+                        &mut aggregate.accumulator,
+                        signature,
+                    );
+                    aggregate.count += 1;
+                }
+                aggregate
+            }
+            Entry::Vacant(entry) => {
+                let mut signers = SignerBitmap::new(validators);
+                signers.insert(index);
+                entry.insert(VoteAggregate {
+                    accumulator: Scheme::accumulator(signature),
+                    signers,
+                    count: 1,
+                })
+            }
+        };
+        self.validator_set
+            .is_supermajority(aggregate.count)
+    }
+
+    /// Running aggregate for a vote, if any signature for it was
+    /// received
+    pub fn get(&self, vote: &Vote) -> Option<&VoteAggregate> {
+        self.aggregates.get(vote)
+    }
+
+    /// Whether the votes for `vote` form a quorum
+    pub fn is_ready(&self, vote: &Vote) -> bool {
+        self.get(vote).is_some_and(|aggregate| {
+            self.validator_set
+                .is_supermajority(aggregate.count)
+        })
+    }
+
+    /// Builds the QC for `vote` from its running aggregate, produced by
+    /// `signer`. Returns None if the votes do not form a quorum yet.
+    pub fn certificate(
+        &mut self,
+        vote: &Vote,
+        signer: &impl ConsensusSigner,
+    ) -> Option<Result<QuorumCertificate, SignError>> {
+        if !self.is_ready(vote) {
+            return None;
+        }
+        let aggregate = self.aggregates.remove(vote)?;
+        Some(QuorumCertificate::from_aggregate(
+            vote.clone(),
+            Scheme::accumulated(&aggregate.accumulator),
+            aggregate.signers,
+            &self.domain,
+            signer,
+        ))
+    }
+}